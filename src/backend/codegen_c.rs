@@ -1,100 +1,1564 @@
-use crate::frontend::{ast::*, token::Token};
+use super::runtime;
+use crate::frontend::{ast::*, span::Span, token::Token, types::{FloatType, IntType, Type}};
+use std::collections::{HashMap, HashSet};
 use std::fmt::{self, Write};
+use std::mem;
 
 pub struct CTranspiler {
     output: String, indent_level: usize,
+    // Kita name -> C name for every local in scope, innermost block last. The first
+    // entry holds module-level locals, which are emitted as file-scope statics.
+    scopes: Vec<HashMap<String, String>>, renamed: usize,
+    // C statements that have to run before the statement being emitted, such
+    // as the `if` computing the value of an `if` expression.
+    hoisted: String,
+    // The enum of every variant, and the variants of every enum in order.
+    variants: HashMap<String, String>, enums: HashMap<String, Vec<String>>,
+    // The methods of every interface, with their signatures without `self`.
+    interfaces: HashMap<String, Vec<(String, Type)>>,
+    // Whether the statement being emitted is in a `switch` within the
+    // innermost loop, and the label after that loop for `break` to jump to.
+    in_switch: bool, loop_exit: Option<String>,
+    // The `typedef`s naming the structs of records and enums, and the C name
+    // and members of every struct standing for an optional or `Result` type.
+    // Either may refer to the other, so they come before all other structs.
+    typedefs: String, value_types: Vec<(String, String)>,
+    // The return type of the function being emitted, which `?` returns errors as.
+    return_type: Type,
+    // The C functions of function literals, and of named functions used as
+    // values, which go in front of the first function definition; and the
+    // named functions and vtables that already have one.
+    lifted: String, wrapped: HashSet<String>,
+    uses_strings: bool, uses_floats: bool, uses_math: bool, uses_arrays: bool, uses_records: bool, uses_errors: bool, uses_closures: bool,
+    bounds_checks: bool,
+}
+
+impl Default for CTranspiler {
+    fn default() -> Self { Self::new() }
 }
 
 impl CTranspiler {
-    pub fn new() -> Self { Self { output: String::new(), indent_level: 1 } }
+    pub fn new() -> Self {
+        Self {
+            output: String::new(), indent_level: 1, scopes: vec![HashMap::new()], renamed: 0, hoisted: String::new(),
+            variants: HashMap::new(), enums: HashMap::new(), interfaces: HashMap::new(), in_switch: false, loop_exit: None, typedefs: String::new(), value_types: Vec::new(),
+            return_type: Type::Void, lifted: String::new(), wrapped: HashSet::new(), uses_strings: false, uses_floats: false, uses_math: false,
+            uses_arrays: false, uses_records: false, uses_errors: false, uses_closures: false, bounds_checks: true,
+        }
+    }
+
+    /// Whether array indices are checked at run time. They are by default.
+    pub fn with_bounds_checks(mut self, enabled: bool) -> Self {
+        self.bounds_checks = enabled;
+        self
+    }
 
     pub fn transpile(&mut self, program: Program) -> Result<String, fmt::Error> {
-        writeln!(&mut self.output, "#include <stdio.h>")?;
-        writeln!(&mut self.output, "#include <stdint.h>")?;
-        writeln!(&mut self.output, "#include <stdbool.h>\n")?;
+        self.transpile_program(program)?;
+        // The runtime support code is only included once we know it is needed.
+        let mut c = String::new();
+        writeln!(&mut c, "#include <stdio.h>")?;
+        writeln!(&mut c, "#include <stdint.h>")?;
+        writeln!(&mut c, "#include <stdbool.h>")?;
+        writeln!(&mut c)?;
+        // String conversion of floats relies on the number formatting helpers.
+        if self.uses_floats || self.uses_strings { writeln!(&mut c, "{}", runtime::NUMBERS)?; }
+        if self.uses_errors || self.uses_math || self.uses_arrays { writeln!(&mut c, "{}", runtime::ERRORS)?; }
+        if self.uses_math { writeln!(&mut c, "{}", runtime::MATH)?; }
+        if self.uses_strings { writeln!(&mut c, "{}", runtime::STRINGS)?; }
+        if self.uses_arrays {
+            if !self.bounds_checks { writeln!(&mut c, "#define KITA_UNCHECKED")?; }
+            writeln!(&mut c, "{}", runtime::ARRAYS)?;
+        }
+        if self.uses_records { writeln!(&mut c, "{}", runtime::RECORDS)?; }
+        if self.uses_closures { writeln!(&mut c, "{}", runtime::CLOSURES)?; }
+        c.push_str(&self.typedefs);
+        for (c_name, members) in &self.value_types { writeln!(&mut c, "typedef struct {{ {} }} {};", members, c_name)?; }
+        if !self.value_types.is_empty() { writeln!(&mut c)?; }
+        c.push_str(&self.output);
+        Ok(c)
+    }
+
+    fn transpile_program(&mut self, program: Program) -> fmt::Result {
+        let (functions, top_level): (Vec<_>, Vec<_>) = program.into_iter().partition(|s| matches!(s.kind, StatementKind::Function { .. }));
+        self.transpile_types(&top_level)?;
+        // Module-level locals live at file scope so that functions can refer to them.
+        for stmt in &top_level {
+            if let StatementKind::Let { vars, .. } = &stmt.kind {
+                for var in vars.iter().filter(|var| !Self::discards(var, vars)) {
+                    let c_name = self.declare_local(&var.name);
+                    let c_type = self.c_type(&var.ty);
+                    writeln!(&mut self.output, "static {} {};", c_type, c_name)?;
+                }
+            }
+        }
+        if top_level.iter().any(|s| matches!(s.kind, StatementKind::Let { .. })) { writeln!(&mut self.output)?; }
+        for func in &functions {
+            if let StatementKind::Function { name, receiver, params, ret, .. } = &func.kind {
+                let params: Vec<_> = params.iter().map(|p| (Self::c_ident(&p.name), &p.ty)).collect();
+                self.function_signature(&Self::function_name(name, receiver.as_deref()), &params, ret)?;
+                writeln!(&mut self.output, ";")?;
+            }
+        }
+        if !functions.is_empty() { writeln!(&mut self.output)?; }
+        let lifted_at = self.output.len();
+        for func in &functions {
+            if let StatementKind::Function { name, receiver, params, body, ret, .. } = &func.kind {
+                self.scopes.push(HashMap::new());
+                self.return_type = ret.clone();
+                let c_params: Vec<_> = params.iter().map(|p| (self.declare_local(&p.name), &p.ty)).collect();
+                self.function_signature(&Self::function_name(name, receiver.as_deref()), &c_params, ret)?;
+                writeln!(&mut self.output, " {{")?;
+                self.share_params(params)?;
+                for stmt in body { self.transpile_statement(stmt)?; }
+                writeln!(&mut self.output, "}}\n")?;
+                self.scopes.pop();
+            }
+        }
+        self.return_type = Type::Int(IntType::I32);
         writeln!(&mut self.output, "int main() {{")?;
-        for stmt in program { self.transpile_statement(&stmt)?; }
+        for stmt in &top_level {
+            if let StatementKind::Let { vars, values, .. } = &stmt.kind {
+                self.hoisting(|this| {
+                    for (var, init) in this.unpack(vars, values)? {
+                        let target = if Self::discards(var, vars) { "(void)".to_string() } else { format!("{} = ", this.resolve(&var.name)) };
+                        this.indent()?;
+                        writeln!(&mut this.output, "{}{};", target, init)?;
+                    }
+                    Ok(())
+                })?;
+            } else {
+                self.transpile_statement(stmt)?;
+            }
+        }
         writeln!(&mut self.output, "    return 0;")?;
         writeln!(&mut self.output, "}}")?;
-        Ok(self.output.clone())
+        let lifted = mem::take(&mut self.lifted);
+        self.output.insert_str(lifted_at, &lifted);
+        Ok(())
+    }
+
+    /// Pairs the variables of a `local` with the C expressions of their
+    /// initial `values`, in order; a discarded value is still evaluated.
+    /// Several variables with a single value take the values of the struct the
+    /// function returns, which is hoisted into a temporary.
+    fn unpack<'v>(&mut self, vars: &'v [Variable], values: &[Expression]) -> Result<Vec<(&'v Variable, String)>, fmt::Error> {
+        let [value] = values else {
+            let inits = self.ordered_operands(&values.iter().collect::<Vec<_>>())?;
+            return Ok(vars.iter().zip(inits).collect());
+        };
+        let init = self.expression_to_string(value)?;
+        if let [var] = vars { return Ok(vec![(var, init)]); }
+        let (temp, c_type) = (self.fresh_name("values"), self.c_type(&value.ty));
+        let outer = mem::take(&mut self.output);
+        self.indent()?;
+        writeln!(&mut self.output, "{} {} = {};", c_type, temp, init)?;
+        let code = mem::replace(&mut self.output, outer);
+        self.hoisted.push_str(&code);
+        // With every value discarded, the call is all that is left.
+        if vars.iter().all(|var| Self::discards(var, vars)) { return Ok(vec![(&vars[0], temp)]); }
+        Ok(vars.iter().enumerate().filter(|(_, var)| !Self::discards(var, vars)).map(|(i, var)| (var, format!("{}.v{}", temp, i + 1))).collect())
+    }
+
+    /// Whether `var` of the variables `vars` of a `local` discards its value.
+    fn discards(var: &Variable, vars: &[Variable]) -> bool { vars.len() > 1 && var.name == "_" }
+
+    /// Moves the parameters that closures share to the heap, at the start of
+    /// a function's body. Uses of such a parameter go through the pointer.
+    fn share_params(&mut self, params: &[Param]) -> fmt::Result {
+        for param in params.iter().filter(|p| p.shared) {
+            let (value, c_type, boxed) = (self.resolve(&param.name), self.c_type(&param.ty), self.fresh_name("shared"));
+            let copy = self.heap_copy(&c_type, &value);
+            self.indent()?;
+            writeln!(&mut self.output, "{} *{} = {};", c_type, boxed, copy)?;
+            self.scopes.last_mut().expect("scope stack is never empty").insert(param.name.clone(), format!("(*{})", boxed));
+        }
+        Ok(())
+    }
+
+    /// A C expression copying `value`, of C type `c_type`, to the heap.
+    fn heap_copy(&mut self, c_type: &str, value: &str) -> String {
+        self.uses_records = true;
+        format!("kita_record_new(({t}[]){{ {} }}, sizeof({t}))", value, t = c_type)
+    }
+
+    /// A pointer to the variable that `access`, as returned by `resolve`, names.
+    fn address_of(access: &str) -> String {
+        match access.strip_prefix("(*").and_then(|pointer| pointer.strip_suffix(')')) {
+            Some(pointer) => pointer.to_string(),
+            None => format!("&{}", access),
+        }
+    }
+
+    /// Whether `name` is a variable rather than a function or variant.
+    fn is_variable(&self, name: &str) -> bool { self.scopes.iter().any(|s| s.contains_key(name)) }
+
+    /// The C type of a pointer to the function of a closure of type `ty`.
+    fn function_pointer(&mut self, ty: &Type) -> String {
+        let Type::Function { params, ret } = ty else { unreachable!("sema only calls functions") };
+        let params: String = params.iter().map(|param| format!(", {}", self.c_type(param))).collect();
+        format!("{} (*)(void *{})", self.c_type(ret), params)
+    }
+
+    /// Writes a call of the closure `closure` of type `ty`: its function is
+    /// called with its environment before the arguments.
+    fn transpile_closure_call(&mut self, closure: &str, ty: &Type, arguments: &[Expression]) -> fmt::Result {
+        let pointer = self.function_pointer(ty);
+        let arguments = self.ordered_operands(&arguments.iter().collect::<Vec<_>>())?;
+        write!(&mut self.output, "(({})({}).fn)(({}).env", pointer, closure, closure)?;
+        for arg in arguments { write!(&mut self.output, ", {}", arg)?; }
+        write!(&mut self.output, ")")
+    }
+
+    /// Emits the C function of a function literal, taking its environment as
+    /// its first parameter, and writes the closure: the function with an
+    /// environment holding the current values of the captured variables, or
+    /// pointers to those it shares. The environment is allocated on the stack
+    /// unless the closure `escapes`.
+    fn transpile_function_literal(&mut self, params: &[Param], body: &BlockStatement, captures: &[Capture], escapes: bool, ty: &Type) -> fmt::Result {
+        let Type::Function { ret, .. } = ty else { unreachable!("sema types function literals as functions") };
+        self.uses_closures = true;
+        let (lambda, env_type) = (self.fresh_name("lambda"), self.fresh_name("env"));
+        let mut members = String::new();
+        for capture in captures {
+            let c_type = self.c_type(&capture.ty);
+            write!(&mut members, " {}{} {};", c_type, if capture.by_reference { " *" } else { "" }, Self::c_ident(&capture.name))?;
+        }
+        // The body sees only module-level names besides its own and the captured ones.
+        let mut inner = HashMap::new();
+        for capture in captures {
+            let access = format!("kita_env->{}", Self::c_ident(&capture.name));
+            inner.insert(capture.name.clone(), if capture.by_reference { format!("(*{})", access) } else { access });
+        }
+        let module = self.scopes[0].clone();
+        let scopes = mem::replace(&mut self.scopes, vec![module, inner, HashMap::new()]);
+        let (outer, indent_level) = (mem::take(&mut self.output), mem::replace(&mut self.indent_level, 1));
+        let (in_switch, loop_exit) = (mem::replace(&mut self.in_switch, false), self.loop_exit.take());
+        let return_type = mem::replace(&mut self.return_type, (**ret).clone());
+        let mut result = Ok(());
+        if !captures.is_empty() { result = writeln!(&mut self.output, "typedef struct {{{} }} {};\n", members, env_type); }
+        let c_params: Vec<_> = params.iter().map(|p| (self.declare_local(&p.name), &p.ty)).collect();
+        let c_params: String = c_params.into_iter().map(|(name, ty)| format!(", {} {}", self.c_type(ty), name)).collect();
+        let c_ret = self.c_type(ret);
+        result = result.and_then(|_| writeln!(&mut self.output, "static {} {}(void *kita_data{}) {{", c_ret, lambda, c_params));
+        result = result.and_then(|_| if captures.is_empty() {
+            writeln!(&mut self.output, "    (void)kita_data;")
+        } else {
+            writeln!(&mut self.output, "    {} *kita_env = kita_data;", env_type)
+        });
+        result = result.and_then(|_| self.share_params(params));
+        for stmt in body { result = result.and_then(|_| self.transpile_statement(stmt)); }
+        result = result.and_then(|_| writeln!(&mut self.output, "}}\n"));
+        let code = mem::replace(&mut self.output, outer);
+        self.lifted.push_str(&code);
+        self.scopes = scopes;
+        self.indent_level = indent_level;
+        self.in_switch = in_switch;
+        self.loop_exit = loop_exit;
+        self.return_type = return_type;
+        result?;
+        if captures.is_empty() { return write!(&mut self.output, "((kita_closure){{ (void (*)(void)){}, NULL }})", lambda); }
+        let values: Vec<_> = captures.iter().map(|capture| {
+            let access = self.resolve(&capture.name);
+            format!(".{} = {}", Self::c_ident(&capture.name), if capture.by_reference { Self::address_of(&access) } else { access })
+        }).collect();
+        let env = if escapes {
+            self.uses_records = true;
+            format!("kita_record_new(&({}){{ {} }}, sizeof({}))", env_type, values.join(", "), env_type)
+        } else {
+            let temp = self.fresh_name("closure");
+            let outer = mem::take(&mut self.output);
+            self.indent()?;
+            writeln!(&mut self.output, "{} {} = {{ {} }};", env_type, temp, values.join(", "))?;
+            let code = mem::replace(&mut self.output, outer);
+            self.hoisted.push_str(&code);
+            format!("&{}", temp)
+        };
+        write!(&mut self.output, "((kita_closure){{ (void (*)(void)){}, {} }})", lambda, env)
+    }
+
+    /// The name of a C function with the signature of a closure's function,
+    /// taking an environment it ignores, that calls the function or variant
+    /// constructor `callee` of type `ty`.
+    fn function_wrapper(&mut self, callee: &str, ty: &Type) -> Result<String, fmt::Error> {
+        let Type::Function { params, ret } = ty else { unreachable!("only functions are wrapped") };
+        let wrapper = format!("kita_fn_{}", callee);
+        if !self.wrapped.insert(wrapper.clone()) { return Ok(wrapper); }
+        let args: Vec<_> = (1..=params.len()).map(|i| format!("_{}", i)).collect();
+        let c_params: String = params.iter().zip(&args).map(|(ty, arg)| format!(", {} {}", self.c_type(ty), arg)).collect();
+        let c_ret = self.c_type(ret);
+        writeln!(&mut self.lifted, "static {} {}(void *kita_data{}) {{", c_ret, wrapper, c_params)?;
+        writeln!(&mut self.lifted, "    (void)kita_data;")?;
+        writeln!(&mut self.lifted, "    {}{}({});", if **ret == Type::Void { "" } else { "return " }, callee, args.join(", "))?;
+        writeln!(&mut self.lifted, "}}\n")?;
+        Ok(wrapper)
+    }
+
+    /// Emits a C struct for every record. Records are used through pointers,
+    /// so declaring all the typedefs first lets the structs refer to each
+    /// other in any order, and lets optionals and results refer to them.
+    fn transpile_types(&mut self, top_level: &[Statement]) -> fmt::Result {
+        for stmt in top_level {
+            if let StatementKind::Interface { name, methods } = &stmt.kind { self.transpile_interface(name, methods)?; }
+        }
+        let types: Vec<_> = top_level.iter().filter(|s| matches!(s.kind, StatementKind::Record { .. } | StatementKind::Enum { .. })).collect();
+        if types.is_empty() { return Ok(()); }
+        self.uses_records = true;
+        for stmt in &types {
+            if let StatementKind::Record { name, .. } | StatementKind::Enum { name, .. } = &stmt.kind {
+                writeln!(&mut self.typedefs, "typedef struct {n} {n};", n = Self::type_name(name))?;
+            }
+        }
+        writeln!(&mut self.typedefs)?;
+        for stmt in &types {
+            match &stmt.kind {
+                StatementKind::Record { name, fields, .. } => {
+                    writeln!(&mut self.output, "struct {} {{", Self::type_name(name))?;
+                    for field in fields.iter() {
+                        let c_type = self.c_type(&field.ty);
+                        writeln!(&mut self.output, "    {} {};", c_type, Self::c_ident(&field.name))?;
+                    }
+                    // C does not allow empty structs.
+                    if fields.is_empty() { writeln!(&mut self.output, "    char kita_unused;")?; }
+                    writeln!(&mut self.output, "}};\n")?;
+                }
+                StatementKind::Enum { name, variants, .. } => self.transpile_enum(name, variants)?,
+                _ => {}
+            }
+        }
+        for stmt in &types {
+            if let StatementKind::Enum { name, variants, .. } = &stmt.kind { self.transpile_constructors(name, variants)?; }
+        }
+        Ok(())
+    }
+
+    /// Emits the vtable struct of an interface, `kita_vtable_Name`, with a
+    /// pointer to a function for each method taking the value as `void *`;
+    /// and `kita_dyn_Name`, a value paired with the vtable of its type.
+    fn transpile_interface(&mut self, name: &str, methods: &[InterfaceMethod]) -> fmt::Result {
+        self.interfaces.insert(name.to_string(), methods.iter().map(|m| (m.name.clone(), m.ty.clone())).collect());
+        writeln!(&mut self.typedefs, "typedef struct kita_vtable_{n} kita_vtable_{n};", n = name)?;
+        writeln!(&mut self.typedefs, "typedef struct {{ void *self; const kita_vtable_{n} *vtable; }} kita_dyn_{n};\n", n = name)?;
+        writeln!(&mut self.output, "struct kita_vtable_{} {{", name)?;
+        for method in methods {
+            let Type::Function { params, ret } = &method.ty else { unreachable!("sema types interface methods as functions") };
+            let params: String = params.iter().map(|param| format!(", {}", self.c_type(param))).collect();
+            let c_ret = self.c_type(ret);
+            writeln!(&mut self.output, "    {} (*{})(void *self{});", c_ret, Self::c_ident(&method.name), params)?;
+        }
+        if methods.is_empty() { writeln!(&mut self.output, "    char kita_unused;")?; }
+        writeln!(&mut self.output, "}};\n")
+    }
+
+    /// The name of the vtable of the record or enum `ty` for `interface`,
+    /// `kita_vtable4_Type_Interface`. It is emitted the first time, together with
+    /// a function for each method that calls the type's own.
+    fn vtable(&mut self, ty: &Type, interface: &str) -> Result<String, fmt::Error> {
+        let vtable = Self::compound_name("kita_vtable", &[&ty.to_string(), interface]);
+        if !self.wrapped.insert(vtable.clone()) { return Ok(vtable); }
+        let mut entries = Vec::new();
+        for (method, sig) in self.interfaces[interface].clone() {
+            let Type::Function { params, ret } = &sig else { unreachable!("sema types interface methods as functions") };
+            let (thunk, c_ret) = (Self::compound_name("kita_vtable", &[&ty.to_string(), interface, &method]), self.c_type(ret));
+            let args: Vec<_> = (1..=params.len()).map(|i| format!("_{}", i)).collect();
+            let c_params: String = params.iter().zip(&args).map(|(ty, arg)| format!(", {} {}", self.c_type(ty), arg)).collect();
+            let args: String = args.iter().map(|arg| format!(", {}", arg)).collect();
+            writeln!(&mut self.lifted, "static {} {}(void *self{}) {{", c_ret, thunk, c_params)?;
+            writeln!(&mut self.lifted, "    {}{}(self{});", if **ret == Type::Void { "" } else { "return " }, Self::function_name(&method, Some(&ty.to_string())), args)?;
+            writeln!(&mut self.lifted, "}}\n")?;
+            entries.push(thunk);
+        }
+        if entries.is_empty() { entries.push("0".to_string()); }
+        writeln!(&mut self.lifted, "static const kita_vtable_{} {} = {{ {} }};\n", interface, vtable, entries.join(", "))?;
+        Ok(vtable)
+    }
+
+    /// Emits an enum as a tagged union: the `tag` says which variant a value
+    /// is, and the fields of variant `V` are `as.V._1`, `as.V._2` and so on.
+    fn transpile_enum(&mut self, name: &str, variants: &[Variant]) -> fmt::Result {
+        self.enums.insert(name.to_string(), variants.iter().map(|v| v.name.clone()).collect());
+        for variant in variants { self.variants.insert(variant.name.clone(), name.to_string()); }
+        writeln!(&mut self.output, "struct {} {{", Self::type_name(name))?;
+        let tags: Vec<_> = variants.iter().map(|v| Self::compound_name("e", &[name, &v.name])).collect();
+        writeln!(&mut self.output, "    enum {{ {} }} tag;", tags.join(", "))?;
+        if variants.iter().any(|v| !v.field_types.is_empty()) {
+            writeln!(&mut self.output, "    union {{")?;
+            for variant in variants.iter().filter(|v| !v.field_types.is_empty()) {
+                let fields: Vec<_> = variant.field_types.iter().enumerate().map(|(i, ty)| format!("{} _{};", self.c_type(ty), i + 1)).collect();
+                writeln!(&mut self.output, "        struct {{ {} }} {};", fields.join(" "), Self::c_ident(&variant.name))?;
+            }
+            writeln!(&mut self.output, "    }} as;")?;
+        }
+        writeln!(&mut self.output, "}};\n")
+    }
+
+    /// Emits a function allocating a value of each variant.
+    fn transpile_constructors(&mut self, name: &str, variants: &[Variant]) -> fmt::Result {
+        let c_name = Self::type_name(name);
+        for variant in variants {
+            let params: Vec<_> = variant.field_types.iter().enumerate().map(|(i, ty)| format!("{} _{}", self.c_type(ty), i + 1)).collect();
+            let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+            writeln!(&mut self.output, "static {} *{}({}) {{", c_name, Self::compound_name("n", &[name, &variant.name]), params)?;
+            writeln!(&mut self.output, "    {} v = {{ .tag = {} }};", c_name, Self::compound_name("e", &[name, &variant.name]))?;
+            for i in 1..=variant.field_types.len() {
+                writeln!(&mut self.output, "    v.as.{}._{i} = _{i};", Self::c_ident(&variant.name), i = i)?;
+            }
+            writeln!(&mut self.output, "    return kita_record_new(&v, sizeof v);")?;
+            writeln!(&mut self.output, "}}\n")?;
+        }
+        Ok(())
+    }
+
+    /// The C function constructing the enum variant `name`, unless a local
+    /// of that name hides the variant.
+    fn variant_constructor(&self, name: &str) -> Option<String> {
+        if self.scopes.iter().any(|s| s.contains_key(name)) { return None; }
+        self.variants.get(name).map(|e| Self::compound_name("n", &[e, name]))
+    }
+
+    /// The C name of a function, or of a method of the type named `receiver`.
+    fn function_name(name: &str, receiver: Option<&str>) -> String {
+        match receiver { Some(receiver) => Self::compound_name("m", &[receiver, name]), None => Self::c_ident(name) }
+    }
+
+    /// Writes a function's C signature given the C names and types of its parameters.
+    fn function_signature(&mut self, c_name: &str, params: &[(String, &Type)], ret: &Type) -> fmt::Result {
+        // A return type left `Unknown` by sema comes from functions that only
+        // return their own recursive calls; `c_type` maps it to `int64_t`.
+        let ret = self.c_type(ret);
+        let params = if params.is_empty() { "void".to_string() } else {
+            params.iter().map(|(p, ty)| format!("{} {}", self.c_type(ty), p)).collect::<Vec<_>>().join(", ")
+        };
+        write!(&mut self.output, "{} {}({})", ret, c_name, params)
+    }
+
+    fn c_type(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int(int) => Self::c_int_type(*int).to_string(),
+            Type::Float(FloatType::F32) => "float".to_string(),
+            Type::Float(FloatType::F64) => "double".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::String => { self.uses_strings = true; "kita_string".to_string() }
+            Type::Array { .. } | Type::Slice(_) => { self.uses_arrays = true; "kita_array *".to_string() }
+            Type::Record(name) | Type::Enum(name) => format!("{} *", Self::type_name(name)),
+            // A null pointer stands for nil where values are pointers anyway.
+            Type::Optional(inner) if inner.is_reference() => self.c_type(inner),
+            Type::Optional(inner) => {
+                let c_name = format!("kita_option_{}", Self::mangle(inner));
+                if !self.value_types.iter().any(|(other, _)| *other == c_name) {
+                    let members = format!("bool has; {} val;", self.c_type(inner));
+                    self.value_types.push((c_name.clone(), members));
+                }
+                c_name
+            }
+            // A successful `Result` of nothing has no `val`.
+            Type::Result { ok, err } => {
+                let c_name = format!("kita_result_{}", Self::mangle(&format!("{}, {}", ok, err)));
+                if !self.value_types.iter().any(|(other, _)| *other == c_name) {
+                    let val = if **ok == Type::Void { String::new() } else { format!(" {} val;", self.c_type(ok)) };
+                    let members = format!("bool ok;{} {} err;", val, self.c_type(err));
+                    self.value_types.push((c_name.clone(), members));
+                }
+                c_name
+            }
+            // The values a function returns are the members `v1`, `v2` and so on.
+            Type::Tuple(elems) => {
+                let names: Vec<String> = elems.iter().map(ToString::to_string).collect();
+                let c_name = format!("kita_tuple_{}", Self::mangle(&names.join(", ")));
+                if !self.value_types.iter().any(|(other, _)| *other == c_name) {
+                    let members: Vec<String> = elems.iter().enumerate().map(|(i, elem)| format!("{} v{};", self.c_type(elem), i + 1)).collect();
+                    self.value_types.push((c_name.clone(), members.join(" ")));
+                }
+                c_name
+            }
+            Type::Dyn(interface) => format!("kita_dyn_{}", interface),
+            Type::Void => "void".to_string(),
+            Type::Function { .. } => { self.uses_closures = true; "kita_closure".to_string() }
+            // Monomorphization leaves no type parameters or generic records behind.
+            Type::Var(_) | Type::Unknown | Type::Error | Type::Param { .. } | Type::Generic { .. } => "int64_t".to_string(),
+        }
+    }
+
+    /// `name` with everything that cannot be part of a C identifier replaced.
+    fn mangle(name: &impl fmt::Display) -> String { name.to_string().replace(|c: char| !c.is_ascii_alphanumeric(), "_") }
+
+    fn c_int_type(int: IntType) -> &'static str {
+        match int {
+            IntType::I8 => "int8_t", IntType::I16 => "int16_t", IntType::I32 => "int32_t", IntType::I64 => "int64_t",
+            IntType::U8 => "uint8_t", IntType::U16 => "uint16_t", IntType::U32 => "uint32_t", IntType::U64 => "uint64_t",
+        }
+    }
+
+    /// Encodes `s` as a C string literal, escaping everything outside printable ASCII.
+    fn c_string_literal(s: &str) -> String {
+        let mut out = String::from("\"");
+        for byte in s.bytes() {
+            match byte {
+                b'"' | b'\\' | b'?' => { out.push('\\'); out.push(byte as char); }
+                0x20..=0x7e => out.push(byte as char),
+                _ => out.push_str(&format!("\\{:03o}", byte)),
+            }
+        }
+        out.push('"');
+        out
+    }
+
+    /// The C name of a Kita identifier. Every one gets the prefix `v_`, so it
+    /// cannot clash with a C keyword, the C library, the runtime or a name the
+    /// compiler makes up: those never start with `v_`.
+    fn c_ident(name: &str) -> String { format!("v_{}", name) }
+
+    /// The C name of a record or enum.
+    fn type_name(name: &str) -> String { format!("t_{}", name) }
+
+    /// A C name starting with `prefix` made of several Kita names, such as a
+    /// method and its receiver. All but the last name are preceded by their
+    /// length, so `A_b` and `c` do not give the same name as `A` and `b_c`.
+    fn compound_name(prefix: &str, names: &[&str]) -> String {
+        let (last, rest) = names.split_last().expect("a compound name has a name");
+        let rest: String = rest.iter().map(|name| format!("{}_{}_", name.len(), name)).collect();
+        format!("{}{}{}", prefix, rest, last)
+    }
+
+    /// Picks the C name for a new local. A Kita local that shadows an outer one
+    /// gets a fresh name, since in C the new variable would already be in scope
+    /// inside its own initializer (`local x = x + 1`). The fresh name `vN_x`
+    /// cannot be the name of another Kita local, which starts with `v_`.
+    fn declare_local(&mut self, name: &str) -> String {
+        let c_name = if self.scopes.iter().any(|s| s.contains_key(name)) {
+            self.renamed += 1;
+            format!("v{}_{}", self.renamed, name)
+        } else { Self::c_ident(name) };
+        self.scopes.last_mut().expect("scope stack is never empty").insert(name.to_string(), c_name.clone());
+        c_name
+    }
+
+    /// A name for a compiler-generated C variable. It starts with `kita_`, so
+    /// it cannot be the C name of a Kita local.
+    fn fresh_name(&mut self, prefix: &str) -> String {
+        self.renamed += 1;
+        format!("kita_{}_{}", prefix, self.renamed)
+    }
+
+    fn resolve(&self, name: &str) -> String {
+        self.scopes.iter().rev().find_map(|s| s.get(name).cloned()).unwrap_or_else(|| Self::c_ident(name))
+    }
+
+    fn transpile_block(&mut self, block: &BlockStatement) -> fmt::Result {
+        self.scopes.push(HashMap::new());
+        self.indent_level += 1;
+        for stmt in block { self.transpile_statement(stmt)?; }
+        self.indent_level -= 1;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Emits a branch of an `if` expression, assigning the value of its final
+    /// expression to `temp`.
+    fn transpile_value_block(&mut self, block: &BlockStatement, temp: &str) -> fmt::Result {
+        self.scopes.push(HashMap::new());
+        self.indent_level += 1;
+        let (value, init) = block.split_last().expect("sema checked that the branch has a value");
+        for stmt in init { self.transpile_statement(stmt)?; }
+        let StatementKind::Expression(value) = &value.kind else { unreachable!("sema checked that the branch ends in an expression") };
+        self.hoisting(|this| {
+            this.indent()?;
+            write!(&mut this.output, "{} = ", temp)?;
+            this.transpile_expression(value)?;
+            writeln!(&mut this.output, ";")
+        })?;
+        self.indent_level -= 1;
+        self.scopes.pop();
+        Ok(())
     }
 
     fn indent(&mut self) -> fmt::Result { write!(&mut self.output, "{}", "    ".repeat(self.indent_level)) }
 
+    /// Runs `emit`, which writes one C statement, and places the code hoisted
+    /// out of its expressions in front of it.
+    fn hoisting(&mut self, emit: impl FnOnce(&mut Self) -> fmt::Result) -> fmt::Result {
+        let outer = mem::take(&mut self.hoisted);
+        let start = self.output.len();
+        let result = emit(self);
+        let hoisted = mem::replace(&mut self.hoisted, outer);
+        self.output.insert_str(start, &hoisted);
+        result
+    }
+
     fn transpile_statement(&mut self, stmt: &Statement) -> fmt::Result {
+        self.hoisting(|this| this.transpile_statement_kind(stmt))
+    }
+
+    fn transpile_statement_kind(&mut self, stmt: &Statement) -> fmt::Result {
         self.indent()?;
-        match stmt {
-            Statement::Let { name, value } => {
-                write!(&mut self.output, "int64_t {} = ", name)?;
+        match &stmt.kind {
+            StatementKind::Let { vars, values, is_const } => {
+                // The initializer must see the bindings that were in scope before
+                // this declaration, so the names are only introduced afterwards.
+                for (i, (var, init)) in self.unpack(vars, values)?.into_iter().enumerate() {
+                    if i > 0 { self.indent()?; }
+                    if Self::discards(var, vars) { writeln!(&mut self.output, "(void){};", init)?; continue; }
+                    let c_name = self.declare_local(&var.name);
+                    let c_type = self.c_type(&var.ty);
+                    match (is_const, c_type.strip_suffix('*')) {
+                        // A variable that closures share lives on the heap.
+                        _ if var.shared => {
+                            let copy = self.heap_copy(&c_type, &init);
+                            writeln!(&mut self.output, "{} *{} = {};", c_type, c_name, copy)?;
+                            self.scopes.last_mut().expect("scope stack is never empty").insert(var.name.clone(), format!("(*{})", c_name));
+                        }
+                        // The pointer is constant, not the array it points to.
+                        (true, Some(pointee)) => writeln!(&mut self.output, "{}*const {} = {};", pointee, c_name, init)?,
+                        (true, None) => writeln!(&mut self.output, "const {} {} = {};", c_type, c_name, init)?,
+                        (false, _) => writeln!(&mut self.output, "{} {} = {};", c_type, c_name, init)?,
+                    }
+                }
+            }
+            // Integer `/=` goes through the checked division, with an
+            // indexed target evaluated only once.
+            StatementKind::Assign { target, op: Some(Token::Slash), value } if matches!(target.ty, Type::Int(_)) => {
+                let value = self.expression_to_string(value)?;
+                let lvalue = self.expression_to_string(target)?;
+                if let ExpressionKind::Index { .. } = target.kind {
+                    let (slot, c_type) = (self.fresh_name("slot"), self.c_type(&target.ty));
+                    let division = self.int_division(&target.ty, &format!("*{}", slot), &value);
+                    writeln!(&mut self.output, "{{ {} *{} = &{}; *{} = {}; }}", c_type, slot, lvalue, slot, division)?;
+                } else {
+                    let division = self.int_division(&target.ty, &lvalue, &value);
+                    writeln!(&mut self.output, "{} = {};", lvalue, division)?;
+                }
+            }
+            StatementKind::Assign { target, op, value } => {
+                match &target.kind {
+                    ExpressionKind::Index { target: array, index } if op.is_none() => self.transpile_element("kita_slot", array, index, &target.ty, &target.span)?,
+                    _ => self.transpile_expression(target)?,
+                }
+                match op {
+                    Some(op) => write!(&mut self.output, " {}= ", Self::op_to_c(op))?,
+                    None => write!(&mut self.output, " = ")?,
+                }
                 self.transpile_expression(value)?;
                 writeln!(&mut self.output, ";")?;
             }
-            Statement::Return(expr) => {
+            // `main` returns C's int, so a bare `return` there exits with 0.
+            StatementKind::Return(values) if values.is_empty() => {
+                writeln!(&mut self.output, "{};", if self.return_type == Type::Void { "return" } else { "return 0" })?;
+            }
+            StatementKind::Return(values) => {
                 write!(&mut self.output, "return ")?;
-                self.transpile_expression(expr)?;
+                if let [value] = &values[..] {
+                    self.transpile_expression(value)?;
+                } else {
+                    let c_type = self.c_type(&self.return_type.clone());
+                    let values = self.ordered_operands(&values.iter().collect::<Vec<_>>())?;
+                    write!(&mut self.output, "({}){{ {} }}", c_type, values.join(", "))?;
+                }
                 writeln!(&mut self.output, ";")?;
             }
-            Statement::Expression(expr) => {
+            StatementKind::Expression(Expression { kind: ExpressionKind::If { condition, consequence, alternative }, .. }) => {
+                write!(&mut self.output, "if (")?;
+                self.transpile_expression(condition)?;
+                writeln!(&mut self.output, ") {{")?;
+                self.transpile_block(consequence)?;
+                self.indent()?;
+                write!(&mut self.output, "}}")?;
+                if let Some(alt) = alternative {
+                    writeln!(&mut self.output, " else {{")?;
+                    self.transpile_block(alt)?;
+                    self.indent()?;
+                    write!(&mut self.output, "}}")?;
+                }
+                writeln!(&mut self.output)?;
+            }
+            StatementKind::Expression(Expression { kind: ExpressionKind::Match { subject, arms }, .. }) => self.transpile_match(subject, arms, None)?,
+            StatementKind::Expression(expr) => {
                 self.transpile_expression(expr)?;
                 writeln!(&mut self.output, ";")?;
             }
+            StatementKind::Function { .. } => writeln!(&mut self.output, "/* nested function declaration */")?,
+            StatementKind::Record { .. } | StatementKind::Enum { .. } | StatementKind::Interface { .. } => writeln!(&mut self.output, "/* nested type declaration */")?,
+            StatementKind::While { .. } | StatementKind::NumericFor { .. } | StatementKind::ForIn { .. } | StatementKind::Repeat { .. } => self.transpile_loop(stmt)?,
+            StatementKind::Break if self.in_switch => {
+                if self.loop_exit.is_none() { self.loop_exit = Some(self.fresh_name("break")); }
+                writeln!(&mut self.output, "goto {};", self.loop_exit.as_deref().unwrap_or_default())?;
+            }
+            StatementKind::Break => writeln!(&mut self.output, "break;")?,
+            StatementKind::Continue => writeln!(&mut self.output, "continue;")?,
         }
         Ok(())
     }
+
+    /// Emits a loop. A `break` in a `match` inside the loop cannot be a C
+    /// `break`, which would only leave the `switch` the match becomes, so it
+    /// jumps to a label after the loop instead.
+    fn transpile_loop(&mut self, stmt: &Statement) -> fmt::Result {
+        let (in_switch, loop_exit) = (mem::replace(&mut self.in_switch, false), self.loop_exit.take());
+        let result = self.transpile_loop_kind(stmt);
+        let exit = mem::replace(&mut self.loop_exit, loop_exit);
+        self.in_switch = in_switch;
+        result?;
+        if let Some(label) = exit {
+            self.indent()?;
+            writeln!(&mut self.output, "{}:;", label)?;
+        }
+        Ok(())
+    }
+
+    fn transpile_loop_kind(&mut self, stmt: &Statement) -> fmt::Result {
+        match &stmt.kind {
+            StatementKind::While { condition, body } => {
+                let (condition, setup) = self.expression_with_hoisted(condition)?;
+                if setup.is_empty() {
+                    writeln!(&mut self.output, "while ({}) {{", condition)?;
+                } else {
+                    // The condition needs statements of its own, which have to
+                    // run before every check.
+                    writeln!(&mut self.output, "for (;;) {{")?;
+                    self.output.push_str(&setup);
+                    self.indent()?;
+                    writeln!(&mut self.output, "    if (!({})) break;", condition)?;
+                }
+                self.transpile_block(body)?;
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+            }
+            StatementKind::NumericFor { var, start, stop, step: step_expr, body } => {
+                // Bounds and step are evaluated once, before the loop variable
+                // exists, and a zero step is an error, as in Lua.
+                let start = self.expression_to_string(start)?;
+                let stop = self.expression_to_string(stop)?;
+                let step = step_expr.as_ref().map(|s| self.expression_to_string(s)).transpose()?;
+                let ty = self.for_var_type(stmt);
+                let c_type = self.c_type(&ty);
+                let (first, limit) = (self.fresh_name("start"), self.fresh_name("limit"));
+                let step_var = step.as_ref().map(|_| self.fresh_name("step"));
+                let mut setup = vec![match (&step, &step_var) {
+                    (Some(step), Some(s)) => format!("{} {} = {}, {} = {}, {} = {};", c_type, first, start, limit, stop, s, step),
+                    _ => format!("{} {} = {}, {} = {};", c_type, first, start, limit, stop),
+                }];
+                if let (Some(s), Some(step_expr)) = (&step_var, step_expr) {
+                    self.uses_errors = true;
+                    setup.push(format!("if ({} == 0) kita_raise({}, \"'for' step is zero\");", s, Self::c_location(&step_expr.span)));
+                }
+                // An integer loop counts its iterations, computed up front in
+                // unsigned arithmetic like Lua 5.4 does, so that the loop
+                // variable never steps past the limit and cannot overflow.
+                let (count, index) = (self.fresh_name("count"), self.fresh_name("i"));
+                if matches!(ty, Type::Int(_)) {
+                    let (runs, span) = match &step_var {
+                        None => (format!("{} <= {}", first, limit), format!("(uint64_t){} - (uint64_t){}", limit, first)),
+                        Some(s) => (
+                            format!("{s} > 0 ? {f} <= {l} : {f} >= {l}", s = s, f = first, l = limit),
+                            format!("{s} > 0 ? ((uint64_t){l} - (uint64_t){f}) / (uint64_t){s} : ((uint64_t){f} - (uint64_t){l}) / ((uint64_t)-({s} + 1) + 1u)", s = s, f = first, l = limit),
+                        ),
+                    };
+                    setup.push(format!("bool {c}_runs = {}; uint64_t {c} = {c}_runs ? {} : 0;", runs, span, c = count));
+                }
+                let outer = mem::take(&mut self.output);
+                for line in &setup {
+                    self.indent()?;
+                    writeln!(&mut self.output, "{}", line)?;
+                }
+                let code = mem::replace(&mut self.output, outer);
+                self.hoisted.push_str(&code);
+                self.scopes.push(HashMap::new());
+                let var = self.declare_local(var);
+                if matches!(ty, Type::Int(_)) {
+                    let step = step_var.as_deref().map_or(String::new(), |s| format!(" * (uint64_t){}", s));
+                    writeln!(&mut self.output, "for (uint64_t {i} = 0; {c}_runs && {i} <= {c}; {i}++) {{", i = index, c = count)?;
+                    self.indent_level += 1;
+                    self.indent()?;
+                    writeln!(&mut self.output, "{t} {} = ({t})((uint64_t){} + {}{});", var, first, index, step, t = c_type)?;
+                    self.indent_level -= 1;
+                } else {
+                    match &step_var {
+                        None => writeln!(&mut self.output, "for ({t} {v} = {f}; {v} <= {l}; {v}++) {{", t = c_type, v = var, f = first, l = limit)?,
+                        Some(s) => writeln!(&mut self.output, "for ({t} {v} = {f}; {s} > 0 ? {v} <= {l} : {v} >= {l}; {v} += {s}) {{", t = c_type, v = var, f = first, l = limit, s = s)?,
+                    }
+                }
+                self.transpile_block(body)?;
+                self.scopes.pop();
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+            }
+            StatementKind::ForIn { index, value, iterable, body } => {
+                let ExpressionKind::Call { arguments, .. } = &iterable.kind else { unreachable!("sema only accepts 'ipairs' calls") };
+                let array = self.expression_to_string(&arguments[0])?;
+                let items = self.fresh_name("items");
+                writeln!(&mut self.output, "{{")?;
+                self.indent_level += 1;
+                self.indent()?;
+                writeln!(&mut self.output, "kita_array *{} = {};", items, array)?;
+                self.scopes.push(HashMap::new());
+                let index = self.declare_local(index);
+                self.indent()?;
+                writeln!(&mut self.output, "for (int64_t {i} = 1; {i} <= {a}->len; {i}++) {{", i = index, a = items)?;
+                if let Some(value) = value {
+                    let elem = arguments[0].ty.element().cloned().unwrap_or(Type::I64);
+                    let c_type = self.c_type(&elem);
+                    let value = self.declare_local(value);
+                    self.indent()?;
+                    writeln!(&mut self.output, "    {t} {} = (({t} *){}->data)[{} - 1];", value, items, index, t = c_type)?;
+                }
+                self.transpile_block(body)?;
+                self.scopes.pop();
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+                self.indent_level -= 1;
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+            }
+            StatementKind::Repeat { body, condition } => {
+                // `until` may refer to the body's locals, so it is checked inside
+                // the loop's block rather than in a `do ... while` condition.
+                writeln!(&mut self.output, "for (;;) {{")?;
+                self.scopes.push(HashMap::new());
+                self.indent_level += 1;
+                for stmt in body { self.transpile_statement(stmt)?; }
+                self.hoisting(|this| {
+                    this.indent()?;
+                    write!(&mut this.output, "if (")?;
+                    this.transpile_expression(condition)?;
+                    writeln!(&mut this.output, ") break;")
+                })?;
+                self.indent_level -= 1;
+                self.scopes.pop();
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+            }
+            _ => unreachable!("only loops are passed to transpile_loop"),
+        }
+        Ok(())
+    }
+
+    /// Emits a `match` as a `switch` on the tag of an enum, or on an integer or
+    /// boolean, with a `case` for each variant or literal the arms test for.
+    /// Each case tries the arms that may apply to it in order. Other values
+    /// cannot be switched on, so their arms are all tried under `default`.
+    /// With a `temp`, the value of the arm that matches is assigned to it.
+    fn transpile_match(&mut self, subject: &Expression, arms: &[MatchArm], temp: Option<&str>) -> fmt::Result {
+        let value = self.fresh_name("subject");
+        let c_type = self.c_type(&subject.ty);
+        let init = self.expression_to_string(subject)?;
+        writeln!(&mut self.output, "{{")?;
+        self.indent_level += 1;
+        self.indent()?;
+        writeln!(&mut self.output, "{} {} = {};", c_type, value, init)?;
+        let catch_all = |arm: &&MatchArm| matches!(arm.pattern.kind, PatternKind::Wildcard | PatternKind::Binding(_));
+        // The `case` labels, each with the arms that may apply to it.
+        let mut cases: Vec<(String, Vec<&MatchArm>)> = Vec::new();
+        let key = match &subject.ty {
+            Type::Enum(name) => {
+                for variant in self.enums.get(name).cloned().unwrap_or_default() {
+                    let applicable = arms.iter().filter(|arm| catch_all(arm) || matches!(&arm.pattern.kind, PatternKind::Variant { name, .. } if *name == variant)).collect();
+                    cases.push((format!("case {}:", Self::compound_name("e", &[name, &variant])), applicable));
+                }
+                format!("{}->tag", value)
+            }
+            Type::Result { .. } => {
+                for (variant, label) in [("ok", "case true:"), ("error", "case false:")] {
+                    let applicable = arms.iter().filter(|arm| catch_all(arm) || matches!(&arm.pattern.kind, PatternKind::Variant { name, .. } if name == variant)).collect();
+                    cases.push((label.to_string(), applicable));
+                }
+                format!("(int){}.ok", value)
+            }
+            Type::Int(_) | Type::Bool => {
+                for arm in arms {
+                    let PatternKind::Literal(literal) = &arm.pattern.kind else { continue };
+                    let label = format!("case {}:", self.expression_to_string(literal)?);
+                    if cases.iter().any(|(other, _)| *other == label) { continue; }
+                    let applicable = arms.iter().filter(|arm| catch_all(arm) || matches!(&arm.pattern.kind, PatternKind::Literal(other) if other.kind == literal.kind)).collect();
+                    cases.push((label, applicable));
+                }
+                cases.push(("default:".to_string(), arms.iter().filter(catch_all).collect()));
+                if subject.ty == Type::Bool { format!("(int){}", value) } else { value.clone() }
+            }
+            _ => {
+                cases.push(("default:".to_string(), arms.iter().collect()));
+                "0".to_string()
+            }
+        };
+        // Only for strings and floats is the `case` not enough to pick an arm's literal.
+        let keyed = matches!(subject.ty, Type::Enum(_) | Type::Result { .. } | Type::Int(_) | Type::Bool);
+        self.indent()?;
+        writeln!(&mut self.output, "switch ({}) {{", key)?;
+        let in_switch = mem::replace(&mut self.in_switch, true);
+        for (label, applicable) in cases {
+            self.indent()?;
+            writeln!(&mut self.output, "{}", label)?;
+            self.indent_level += 1;
+            let mut matched = false;
+            for arm in applicable {
+                if self.transpile_arm(arm, &value, keyed, temp)? { matched = true; break; }
+            }
+            if !matched {
+                self.indent()?;
+                writeln!(&mut self.output, "break;")?;
+            }
+            self.indent_level -= 1;
+        }
+        self.in_switch = in_switch;
+        self.indent()?;
+        writeln!(&mut self.output, "}}")?;
+        self.indent_level -= 1;
+        self.indent()?;
+        writeln!(&mut self.output, "}}")
+    }
+
+    /// Emits an arm of a `match` on `value`, leaving the `switch` when it
+    /// matches. Returns whether it always matches, so that the arms after it
+    /// need not be emitted.
+    fn transpile_arm(&mut self, arm: &MatchArm, value: &str, keyed: bool, temp: Option<&str>) -> Result<bool, fmt::Error> {
+        let (mut conditions, mut bindings) = (Vec::new(), Vec::new());
+        self.pattern_tests(&arm.pattern, value, keyed, &mut conditions, &mut bindings)?;
+        self.indent()?;
+        if conditions.is_empty() { writeln!(&mut self.output, "{{")?; } else { writeln!(&mut self.output, "if ({}) {{", conditions.join(" && "))?; }
+        self.scopes.push(HashMap::new());
+        for (name, ty, access) in bindings {
+            let c_type = self.c_type(&ty);
+            let c_name = self.declare_local(&name);
+            self.indent()?;
+            writeln!(&mut self.output, "    {} {} = {};", c_type, c_name, access)?;
+        }
+        let outer = self.indent_level;
+        if let Some(guard) = &arm.guard {
+            self.hoisting(|this| {
+                this.indent()?;
+                write!(&mut this.output, "    if (")?;
+                this.transpile_expression(guard)?;
+                writeln!(&mut this.output, ") {{")
+            })?;
+            self.indent_level += 1;
+        }
+        match temp {
+            Some(temp) => self.transpile_value_block(&arm.body, temp)?,
+            None => self.transpile_block(&arm.body)?,
+        }
+        self.indent()?;
+        writeln!(&mut self.output, "    break;")?;
+        if arm.guard.is_some() {
+            self.indent_level = outer;
+            self.indent()?;
+            writeln!(&mut self.output, "    }}")?;
+        }
+        self.scopes.pop();
+        self.indent()?;
+        writeln!(&mut self.output, "}}")?;
+        Ok(conditions.is_empty() && arm.guard.is_none())
+    }
+
+    /// Collects the C conditions under which `access` matches `pattern`, each
+    /// of which may rely on the ones before it, and the Kita names, types and
+    /// C values of the pattern's bindings. If `keyed`, the `switch` already
+    /// checked the variant or literal at the top of the pattern.
+    fn pattern_tests(&mut self, pattern: &Pattern, access: &str, keyed: bool, conditions: &mut Vec<String>, bindings: &mut Vec<(String, Type, String)>) -> fmt::Result {
+        match &pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(name) => bindings.push((name.clone(), pattern.ty.clone(), access.to_string())),
+            PatternKind::Literal(_) if keyed => {}
+            PatternKind::Literal(literal) => {
+                let literal = self.expression_to_string(literal)?;
+                conditions.push(if pattern.ty == Type::String { format!("kita_compare({}, {}) == 0", access, literal) } else { format!("{} == {}", access, literal) });
+            }
+            // The value of a `Result` is its `val` or its `err`.
+            PatternKind::Variant { name, fields } if matches!(pattern.ty, Type::Result { .. }) => {
+                let ok = name == "ok";
+                if !keyed { conditions.push(format!("{}{}.ok", if ok { "" } else { "!" }, access)); }
+                if let Some(field) = fields.first() {
+                    self.pattern_tests(field, &format!("{}.{}", access, if ok { "val" } else { "err" }), false, conditions, bindings)?;
+                }
+            }
+            PatternKind::Variant { name, fields } => {
+                let Type::Enum(enum_name) = &pattern.ty else { unreachable!("sema only accepts variant patterns for enums and results") };
+                if !keyed { conditions.push(format!("{}->tag == {}", access, Self::compound_name("e", &[enum_name, name]))); }
+                for (i, field) in fields.iter().enumerate() {
+                    self.pattern_tests(field, &format!("{}->as.{}._{}", access, Self::c_ident(name), i + 1), false, conditions, bindings)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The type of a numeric `for` loop's control variable, which sema made the
+    /// common type of all of the loop's bounds.
+    fn for_var_type(&self, stmt: &Statement) -> Type {
+        let StatementKind::NumericFor { start, stop, step, .. } = &stmt.kind else { return Type::I64 };
+        [Some(start), Some(stop), step.as_ref()].into_iter().flatten()
+            .try_fold(Type::Unknown, |acc, bound| acc.promote(&bound.ty))
+            .filter(|ty| *ty != Type::Unknown)
+            .unwrap_or(Type::I64)
+    }
+
+    /// Like `expression_to_string`, but returns the code hoisted out of `expr`
+    /// instead of placing it in front of the current statement. The code is
+    /// indented for a nested block.
+    fn expression_with_hoisted(&mut self, expr: &Expression) -> Result<(String, String), fmt::Error> {
+        let outer = mem::take(&mut self.hoisted);
+        self.indent_level += 1;
+        let result = self.expression_to_string(expr);
+        self.indent_level -= 1;
+        let hoisted = mem::replace(&mut self.hoisted, outer);
+        result.map(|expr| (expr, hoisted))
+    }
+
+    /// The C expressions of `operands`, which Lua evaluates left to right but C
+    /// in no particular order. An operand is stored in a temporary, in front
+    /// of the code hoisted out of the operands after it, when a later one
+    /// might affect or observe it.
+    fn ordered_operands(&mut self, operands: &[&Expression]) -> Result<Vec<String>, fmt::Error> {
+        let (mut values, mut unordered): (Vec<String>, Vec<usize>) = (Vec::new(), Vec::new());
+        for operand in operands {
+            let mark = self.hoisted.len();
+            let value = self.expression_to_string(operand)?;
+            if Self::has_effects(operand) || self.hoisted.len() > mark {
+                let outer = mem::take(&mut self.output);
+                for i in unordered.drain(..) {
+                    let (temp, c_type) = (self.fresh_name("operand"), self.c_type(&operands[i].ty));
+                    self.indent()?;
+                    writeln!(&mut self.output, "{} {} = {};", c_type, temp, values[i])?;
+                    values[i] = temp;
+                }
+                let code = mem::replace(&mut self.output, outer);
+                self.hoisted.insert_str(mark, &code);
+            }
+            if !matches!(operand.kind, ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_) | ExpressionKind::StringLiteral(_)
+                | ExpressionKind::Boolean(_) | ExpressionKind::Nil | ExpressionKind::Instance { .. }) {
+                unordered.push(values.len());
+            }
+            values.push(value);
+        }
+        Ok(values)
+    }
+
+    /// Whether evaluating `expr` might do something another operand could
+    /// tell apart: call a function, raise an error or run hoisted code.
+    fn has_effects(expr: &Expression) -> bool {
+        match &expr.kind {
+            ExpressionKind::Identifier(_) | ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_) | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::Boolean(_) | ExpressionKind::Nil | ExpressionKind::Instance { .. } | ExpressionKind::FunctionLiteral { .. } => false,
+            ExpressionKind::Prefix { right: inner, .. } | ExpressionKind::Field { target: inner, .. } | ExpressionKind::Wrap(inner)
+            | ExpressionKind::Unwrap(inner) | ExpressionKind::Dyn(inner) => Self::has_effects(inner),
+            ExpressionKind::Infix { op: Token::Slash | Token::FloorDiv | Token::Percent, .. } if matches!(expr.ty, Type::Int(_)) => true,
+            ExpressionKind::Infix { left, right, .. } => Self::has_effects(left) || Self::has_effects(right),
+            ExpressionKind::ArrayLiteral(elements) => elements.iter().any(Self::has_effects),
+            ExpressionKind::RecordLiteral { fields, .. } => fields.iter().any(|field| Self::has_effects(&field.value)),
+            ExpressionKind::Call { .. } | ExpressionKind::MethodCall { .. } | ExpressionKind::If { .. } | ExpressionKind::Match { .. }
+            | ExpressionKind::Index { .. } | ExpressionKind::Slice { .. } | ExpressionKind::Try(_) => true,
+        }
+    }
+
+    /// Transpiles `expr` into a separate string instead of the output buffer.
+    fn expression_to_string(&mut self, expr: &Expression) -> Result<String, fmt::Error> {
+        let mut out = String::new();
+        mem::swap(&mut self.output, &mut out);
+        let result = self.transpile_expression(expr);
+        mem::swap(&mut self.output, &mut out);
+        result.map(|_| out)
+    }
     
     fn transpile_expression(&mut self, expr: &Expression) -> fmt::Result {
-        match expr {
-            Expression::Identifier(name) => write!(&mut self.output, "{}", name)?,
-            Expression::IntegerLiteral(val) => write!(&mut self.output, "{}", val)?,
-            Expression::Boolean(val) => write!(&mut self.output, "{}", val)?,
-            Expression::Infix { op, left, right } => {
+        match &expr.kind {
+            ExpressionKind::Identifier(name) => match self.variant_constructor(name) {
+                // A function used as a value becomes a closure without an environment.
+                constructor if matches!(expr.ty, Type::Function { .. }) && (constructor.is_some() || !self.is_variable(name)) => {
+                    let wrapper = self.function_wrapper(&constructor.unwrap_or_else(|| Self::c_ident(name)), &expr.ty)?;
+                    write!(&mut self.output, "((kita_closure){{ (void (*)(void)){}, NULL }})", wrapper)?;
+                }
+                Some(constructor) => write!(&mut self.output, "{}()", constructor)?,
+                None => { let c_name = self.resolve(name); write!(&mut self.output, "{}", c_name)? }
+            },
+            ExpressionKind::FunctionLiteral { params, body, captures, escapes, .. } => self.transpile_function_literal(params, body, captures, *escapes, &expr.ty)?,
+            ExpressionKind::IntegerLiteral(val) => match expr.ty {
+                // Spell out the type so that C does not truncate or sign-extend
+                // large constants.
+                Type::Int(IntType::I64) if *val == i128::from(i64::MIN) => write!(&mut self.output, "INT64_MIN")?,
+                Type::Int(IntType::I64) => write!(&mut self.output, "INT64_C({})", val)?,
+                Type::Int(IntType::U64) => write!(&mut self.output, "UINT64_C({})", val)?,
+                Type::Float(_) => write!(&mut self.output, "{:?}", *val as f64)?,
+                _ => write!(&mut self.output, "{}", val)?,
+            },
+            ExpressionKind::FloatLiteral(val) => {
+                write!(&mut self.output, "{:?}", val)?;
+                if expr.ty == Type::Float(FloatType::F32) { write!(&mut self.output, "f")?; }
+            }
+            ExpressionKind::StringLiteral(val) => {
+                self.uses_strings = true;
+                write!(&mut self.output, "kita_str({}, {})", Self::c_string_literal(val), val.len())?;
+            }
+            ExpressionKind::Prefix { op: Token::Hash, right } => {
                 write!(&mut self.output, "(")?;
-                self.transpile_expression(left)?;
-                write!(&mut self.output, " {} ", Self::op_to_c(op))?;
+                self.transpile_expression(right)?;
+                write!(&mut self.output, "){}len", if right.ty.element().is_some() { "->" } else { "." })?;
+            }
+            ExpressionKind::Prefix { op, right } => {
+                let cast = Self::narrow_cast(&expr.ty);
+                write!(&mut self.output, "({}{}", cast, Self::op_to_c(op))?;
                 self.transpile_expression(right)?;
                 write!(&mut self.output, ")")?;
             }
-            Expression::If { condition, consequence, alternative } => {
-                write!(&mut self.output, "if (")?;
-                self.transpile_expression(condition)?;
-                writeln!(&mut self.output, ") {{")?;
-                self.indent_level += 1;
-                for stmt in consequence { self.transpile_statement(stmt)?; }
-                self.indent_level -= 1;
+            ExpressionKind::Infix { op: Token::Concat, left, right } => {
+                self.uses_strings = true;
+                let [left_value, right_value] = <[String; 2]>::try_from(self.ordered_operands(&[&**left, &**right])?).expect("two operands");
+                let (left, right) = (Self::string_operand(&left.ty, left_value), Self::string_operand(&right.ty, right_value));
+                write!(&mut self.output, "kita_concat({}, {})", left, right)?;
+            }
+            ExpressionKind::Boolean(val) => write!(&mut self.output, "{}", val)?,
+            ExpressionKind::Nil => match &expr.ty {
+                Type::Optional(inner) if inner.is_reference() => write!(&mut self.output, "NULL")?,
+                ty => { let c_type = self.c_type(ty); write!(&mut self.output, "(({}){{ .has = false }})", c_type)? }
+            },
+            ExpressionKind::Wrap(value) => match &expr.ty {
+                Type::Optional(inner) if inner.is_reference() => self.transpile_expression(value)?,
+                ty => {
+                    let (c_type, flag) = (self.c_type(ty), if matches!(ty, Type::Result { .. }) { "ok" } else { "has" });
+                    write!(&mut self.output, "(({}){{ .{} = true, .val = ", c_type, flag)?;
+                    self.transpile_expression(value)?;
+                    write!(&mut self.output, " }})")?;
+                }
+            },
+            ExpressionKind::Dyn(value) => match &value.ty {
+                // In the copy of a generic function for a `dyn` type, the value already is one.
+                Type::Dyn(_) => self.transpile_expression(value)?,
+                ty => {
+                    let Type::Dyn(interface) = &expr.ty else { unreachable!("sema types conversions to dyn as dyn") };
+                    let vtable = self.vtable(ty, interface)?;
+                    write!(&mut self.output, "((kita_dyn_{}){{ ", interface)?;
+                    self.transpile_expression(value)?;
+                    write!(&mut self.output, ", &{} }})", vtable)?;
+                }
+            },
+            ExpressionKind::Try(value) => {
+                // A failed result is returned right away, before the rest of
+                // the statement runs.
+                let Type::Result { ok, .. } = &value.ty else { unreachable!("sema only accepts '?' on results") };
+                let temp = self.fresh_name("try");
+                let (c_type, ret_type) = (self.c_type(&value.ty), self.c_type(&self.return_type.clone()));
+                let result = self.expression_to_string(value)?;
+                let outer = mem::take(&mut self.output);
                 self.indent()?;
-                write!(&mut self.output, "}}")?;
-                if let Some(alt) = alternative {
-                    writeln!(&mut self.output, " else {{")?;
-                    self.indent_level += 1;
-                    for stmt in alt { self.transpile_statement(stmt)?; }
-                    self.indent_level -= 1;
+                writeln!(&mut self.output, "{} {} = {};", c_type, temp, result)?;
+                self.indent()?;
+                writeln!(&mut self.output, "if (!{t}.ok) return (({}){{ .ok = false, .err = {t}.err }});", ret_type, t = temp)?;
+                let code = mem::replace(&mut self.output, outer);
+                self.hoisted.push_str(&code);
+                if **ok == Type::Void { write!(&mut self.output, "((void)0)")?; } else { write!(&mut self.output, "{}.val", temp)?; }
+            }
+            ExpressionKind::Unwrap(variable) => {
+                if expr.ty.is_reference() { self.transpile_expression(variable)?; } else {
+                    write!(&mut self.output, "(")?;
+                    self.transpile_expression(variable)?;
+                    write!(&mut self.output, ").val")?;
+                }
+            }
+            ExpressionKind::Infix { op: op @ (Token::Eq | Token::NotEq), left, right } if left.kind == ExpressionKind::Nil || right.kind == ExpressionKind::Nil => {
+                let value = if right.kind == ExpressionKind::Nil { left } else { right };
+                // A narrowed variable is tested all the same.
+                let value = match &value.kind { ExpressionKind::Unwrap(variable) => variable, _ => value };
+                let test = self.expression_to_string(value)?;
+                match &value.ty {
+                    Type::Optional(inner) if inner.is_reference() => write!(&mut self.output, "({} {} NULL)", test, Self::op_to_c(op))?,
+                    _ => write!(&mut self.output, "({}({}).has)", if *op == Token::Eq { "!" } else { "" }, test)?,
+                }
+            }
+            ExpressionKind::Infix { op: Token::Or, left, right } if matches!(left.ty, Type::Optional(_)) => self.transpile_default(left, right, &expr.ty)?,
+            ExpressionKind::Infix { op, left, right } if left.ty == Type::String => {
+                // Only comparisons accept strings.
+                let operands = self.ordered_operands(&[&**left, &**right])?;
+                write!(&mut self.output, "(kita_compare({}) {} 0)", operands.join(", "), Self::op_to_c(op))?;
+            }
+            ExpressionKind::Infix { op: op @ (Token::And | Token::Or), left, right } => {
+                let left = self.expression_to_string(left)?;
+                let (right, setup) = self.expression_with_hoisted(right)?;
+                if setup.is_empty() {
+                    write!(&mut self.output, "({} {} {})", left, Self::op_to_c(op), right)?;
+                } else {
+                    // The right operand's hoisted code may only run when the
+                    // left operand does not decide the result on its own.
+                    let temp = self.fresh_name("cond");
+                    let outer = mem::take(&mut self.output);
                     self.indent()?;
-                    write!(&mut self.output, "}}")?;
+                    writeln!(&mut self.output, "bool {} = {};", temp, left)?;
+                    self.indent()?;
+                    writeln!(&mut self.output, "if ({}{}) {{", if *op == Token::Or { "!" } else { "" }, temp)?;
+                    self.output.push_str(&setup);
+                    self.indent()?;
+                    writeln!(&mut self.output, "    {} = {};", temp, right)?;
+                    self.indent()?;
+                    writeln!(&mut self.output, "}}")?;
+                    let code = mem::replace(&mut self.output, outer);
+                    self.hoisted.push_str(&code);
+                    write!(&mut self.output, "{}", temp)?;
                 }
             }
-            Expression::Call { function, arguments } => {
-                 if let Expression::Identifier(name) = &**function {
+            ExpressionKind::Infix { op: Token::Slash, left, right } if matches!(expr.ty, Type::Int(_)) => {
+                let operands = self.ordered_operands(&[&**left, &**right])?;
+                let division = self.int_division(&expr.ty, &operands[0], &operands[1]);
+                write!(&mut self.output, "{}", division)?;
+            }
+            ExpressionKind::Infix { op: op @ (Token::FloorDiv | Token::Percent | Token::Caret | Token::ShiftLeft | Token::ShiftRight), left, right } => {
+                self.uses_math = true;
+                let operands = self.ordered_operands(&[&**left, &**right])?;
+                let (left, right) = (&operands[0], &operands[1]);
+                let cast = self.exact_cast(&expr.ty);
+                match (op, &expr.ty) {
+                    (Token::Caret, _) => write!(&mut self.output, "pow((double)({}), (double)({}))", left, right)?,
+                    (Token::ShiftLeft | Token::ShiftRight, ty) => {
+                        let bits = if let Type::Int(int) = ty { int.bits() } else { 64 };
+                        let sign = if *op == Token::ShiftRight { "-" } else { "" };
+                        write!(&mut self.output, "({}kita_shift_left((uint64_t)({}), {}(int64_t)({}), {}))", cast, left, sign, right, bits)?;
+                    }
+                    (Token::FloorDiv, Type::Float(_)) => write!(&mut self.output, "({}floor({} / {}))", cast, left, right)?,
+                    (_, Type::Float(_)) => write!(&mut self.output, "({}kita_float_mod({}, {}))", cast, left, right)?,
+                    (_, ty) => {
+                        let signed = !matches!(ty, Type::Int(int) if !int.is_signed());
+                        let func = match (op, signed) {
+                            (Token::FloorDiv, true) => "kita_floor_div", (Token::FloorDiv, false) => "kita_unsigned_div",
+                            (_, true) => "kita_mod", (_, false) => "kita_unsigned_mod",
+                        };
+                        write!(&mut self.output, "({}{}({}, {}))", cast, func, left, right)?;
+                    }
+                }
+            }
+            ExpressionKind::Infix { op, left, right } => {
+                let cast = Self::narrow_cast(&expr.ty);
+                let operands = self.ordered_operands(&[&**left, &**right])?;
+                // A binary `~` is exclusive or, which C spells `^`.
+                let c_op = if *op == Token::Tilde { "^" } else { Self::op_to_c(op) };
+                let infix = format!("({} {} {})", operands[0], c_op, operands[1]);
+                if cast.is_empty() { write!(&mut self.output, "{}", infix)?; } else { write!(&mut self.output, "({}{})", cast, infix)?; }
+            }
+            ExpressionKind::If { condition, consequence, alternative: Some(alternative) } => {
+                // C has no expression that can contain statements, so the `if`
+                // is hoisted in front of the current statement and stores its
+                // value in a temporary.
+                let temp = self.fresh_name("if");
+                let c_type = self.c_type(&expr.ty);
+                let condition = self.expression_to_string(condition)?;
+                let outer = mem::take(&mut self.output);
+                self.indent()?;
+                writeln!(&mut self.output, "{} {};", c_type, temp)?;
+                self.indent()?;
+                writeln!(&mut self.output, "if ({}) {{", condition)?;
+                self.transpile_value_block(consequence, &temp)?;
+                self.indent()?;
+                writeln!(&mut self.output, "}} else {{")?;
+                self.transpile_value_block(alternative, &temp)?;
+                self.indent()?;
+                writeln!(&mut self.output, "}}")?;
+                let code = mem::replace(&mut self.output, outer);
+                self.hoisted.push_str(&code);
+                write!(&mut self.output, "{}", temp)?;
+            }
+            ExpressionKind::Call { function, arguments } => {
+                if let ExpressionKind::Identifier(name) = &function.kind {
                     if name == "print" {
-                        write!(&mut self.output, "printf(\"%lld\\n\", ")?;
-                        if let Some(arg) = arguments.get(0) { self.transpile_expression(arg)?; }
+                        if let Some(arg) = arguments.first() { self.transpile_print(arg)?; }
+                    } else if name == "error" {
+                        let c_type = self.c_type(&expr.ty);
+                        write!(&mut self.output, "(({}){{ .ok = false, .err = ", c_type)?;
+                        self.transpile_expression(&arguments[0])?;
+                        write!(&mut self.output, " }})")?;
+                    } else if name == "pcall" {
+                        self.transpile_pcall(arguments, &expr.ty)?;
+                    } else if self.is_variable(name) {
+                        let closure = self.resolve(name);
+                        self.transpile_closure_call(&closure, &function.ty, arguments)?;
+                    } else if let (Some(target @ (Type::Int(_) | Type::Float(_))), Some(arg)) = (Type::from_name(name), arguments.first()) {
+                        let c_type = self.c_type(&target);
+                        write!(&mut self.output, "(({})", c_type)?;
+                        self.transpile_expression(arg)?;
                         write!(&mut self.output, ")")?;
                     } else {
-                        write!(&mut self.output, "{}(", name)?;
-                        // In a real version, we'd loop through arguments here
-                        write!(&mut self.output, ")")?;
+                        let c_name = self.variant_constructor(name).unwrap_or_else(|| self.resolve(name));
+                        let arguments = self.ordered_operands(&arguments.iter().collect::<Vec<_>>())?;
+                        write!(&mut self.output, "{}({})", c_name, arguments.join(", "))?;
                     }
+                } else {
+                    // Any other callee is evaluated once, into a temporary.
+                    let value = self.expression_to_string(function)?;
+                    let temp = self.fresh_name("callee");
+                    let outer = mem::take(&mut self.output);
+                    self.indent()?;
+                    writeln!(&mut self.output, "kita_closure {} = {};", temp, value)?;
+                    let code = mem::replace(&mut self.output, outer);
+                    self.hoisted.push_str(&code);
+                    self.transpile_closure_call(&temp, &function.ty, arguments)?;
                 }
             },
+            ExpressionKind::MethodCall { receiver, method, arguments } => {
+                match (&receiver.ty, method.as_str()) {
+                    (Type::String, "len") | (Type::Array { .. } | Type::Slice(_), "len") => {
+                        write!(&mut self.output, "(")?;
+                        self.transpile_expression(receiver)?;
+                        write!(&mut self.output, "){}len", if receiver.ty == Type::String { "." } else { "->" })?;
+                        return Ok(());
+                    }
+                    (Type::String, "upper" | "lower" | "sub") => write!(&mut self.output, "kita_string_{}(", method)?,
+                    // The method of a `dyn` value is looked up in its vtable;
+                    // the value is hoisted unless it is a variable already.
+                    (Type::Dyn(_), _) => {
+                        let mut value = self.expression_to_string(receiver)?;
+                        if !matches!(receiver.kind, ExpressionKind::Identifier(_)) {
+                            let (temp, c_type) = (self.fresh_name("dyn"), self.c_type(&receiver.ty));
+                            let outer = mem::take(&mut self.output);
+                            self.indent()?;
+                            writeln!(&mut self.output, "{} {} = {};", c_type, temp, value)?;
+                            let code = mem::replace(&mut self.output, outer);
+                            self.hoisted.push_str(&code);
+                            value = temp;
+                        }
+                        let arguments = self.ordered_operands(&arguments.iter().collect::<Vec<_>>())?;
+                        write!(&mut self.output, "{v}.vtable->{}({v}.self", Self::c_ident(method), v = value)?;
+                        for arg in arguments { write!(&mut self.output, ", {}", arg)?; }
+                        return write!(&mut self.output, ")");
+                    }
+                    (ty, _) => write!(&mut self.output, "{}(", Self::function_name(method, Some(&ty.to_string())))?,
+                }
+                let operands = self.ordered_operands(&[&**receiver].into_iter().chain(arguments).collect::<Vec<_>>())?;
+                write!(&mut self.output, "{})", operands.join(", "))?;
+            }
+            ExpressionKind::ArrayLiteral(elements) => {
+                let Type::Array { elem, size } = &expr.ty else { unreachable!("sema types array literals as arrays") };
+                let c_type = self.c_type(elem);
+                write!(&mut self.output, "kita_array_new({}, sizeof({}), ", elements.len(), c_type)?;
+                if elements.is_empty() {
+                    write!(&mut self.output, "NULL")?;
+                } else {
+                    let elements = self.ordered_operands(&elements.iter().collect::<Vec<_>>())?;
+                    write!(&mut self.output, "({}[]){{{}}}", c_type, elements.join(", "))?;
+                }
+                write!(&mut self.output, ", {})", size.is_none())?;
+            }
+            ExpressionKind::Index { target, index } => self.transpile_element("kita_at", target, index, &expr.ty, &expr.span)?,
+            ExpressionKind::Slice { target, start, stop } => {
+                let elem = expr.ty.element().cloned().unwrap_or(Type::I64);
+                let c_type = self.c_type(&elem);
+                let [target, start, stop] = <[String; 3]>::try_from(self.ordered_operands(&[target, start, stop])?).expect("three operands");
+                write!(&mut self.output, "kita_slice({}, (int64_t)({}), (int64_t)({}), sizeof({}), {})", target, start, stop, c_type, Self::c_location(&expr.span))?;
+            }
+            ExpressionKind::RecordLiteral { name, fields } => {
+                // The fields are initialized in a compound literal, which is
+                // then copied to the heap.
+                let c_name = Self::type_name(name);
+                let values = self.ordered_operands(&fields.iter().map(|field| &field.value).collect::<Vec<_>>())?;
+                write!(&mut self.output, "(({n} *)kita_record_new(&({n}){{", n = c_name)?;
+                for (i, (field, value)) in fields.iter().zip(values).enumerate() {
+                    write!(&mut self.output, "{}.{} = {}", if i > 0 { ", " } else { " " }, Self::c_ident(&field.name), value)?;
+                }
+                if fields.is_empty() { write!(&mut self.output, " 0")?; }
+                write!(&mut self.output, " }}, sizeof({})))", c_name)?;
+            }
+            ExpressionKind::Match { subject, arms } => {
+                // Hoisted in front of the current statement, like an `if`.
+                let temp = self.fresh_name("match");
+                let c_type = self.c_type(&expr.ty);
+                let outer = mem::take(&mut self.output);
+                self.indent()?;
+                writeln!(&mut self.output, "{} {};", c_type, temp)?;
+                self.indent()?;
+                self.transpile_match(subject, arms, Some(&temp))?;
+                let code = mem::replace(&mut self.output, outer);
+                self.hoisted.push_str(&code);
+                write!(&mut self.output, "{}", temp)?;
+            }
+            ExpressionKind::Field { target, name } => {
+                write!(&mut self.output, "(")?;
+                self.transpile_expression(target)?;
+                write!(&mut self.output, ")->{}", Self::c_ident(name))?;
+            }
             _ => write!(&mut self.output, "/* unhandled expression */")?,
         }
         Ok(())
     }
 
+    /// Writes `optional or default`, which is the value of `optional` unless it
+    /// is nil. The result has type `ty`, which is optional if `default` is.
+    fn transpile_default(&mut self, optional: &Expression, default: &Expression, ty: &Type) -> fmt::Result {
+        let Type::Optional(inner) = &optional.ty else { unreachable!("sema only accepts defaults for optionals") };
+        let reference = inner.is_reference();
+        let mut value = self.expression_to_string(optional)?;
+        // Anything but a variable is only evaluated once, into a temporary.
+        if !matches!(optional.kind, ExpressionKind::Identifier(_)) {
+            let temp = self.fresh_name("optional");
+            let c_type = self.c_type(&optional.ty);
+            let outer = mem::take(&mut self.output);
+            self.indent()?;
+            writeln!(&mut self.output, "{} {} = {};", c_type, temp, value)?;
+            let code = mem::replace(&mut self.output, outer);
+            self.hoisted.push_str(&code);
+            value = temp;
+        }
+        let has = if reference { format!("{} != NULL", value) } else { format!("{}.has", value) };
+        let present = if reference || matches!(ty, Type::Optional(_)) { value.clone() } else { format!("{}.val", value) };
+        let (default, setup) = self.expression_with_hoisted(default)?;
+        if setup.is_empty() { return write!(&mut self.output, "({} ? {} : {})", has, present, default); }
+        // The default's hoisted code may only run when there is no value.
+        let temp = self.fresh_name("or");
+        let c_type = self.c_type(ty);
+        let outer = mem::take(&mut self.output);
+        self.indent()?;
+        writeln!(&mut self.output, "{} {};", c_type, temp)?;
+        self.indent()?;
+        writeln!(&mut self.output, "if ({}) {{", has)?;
+        self.indent()?;
+        writeln!(&mut self.output, "    {} = {};", temp, present)?;
+        self.indent()?;
+        writeln!(&mut self.output, "}} else {{")?;
+        self.output.push_str(&setup);
+        self.indent()?;
+        writeln!(&mut self.output, "    {} = {};", temp, default)?;
+        self.indent()?;
+        writeln!(&mut self.output, "}}")?;
+        let code = mem::replace(&mut self.output, outer);
+        self.hoisted.push_str(&code);
+        write!(&mut self.output, "{}", temp)
+    }
+
+    /// Writes `pcall(f, ...)`. The arguments are evaluated first, so that only
+    /// the errors raised by the call itself are caught. `kita_handler` points
+    /// at the jump buffer of the innermost `pcall` running.
+    fn transpile_pcall(&mut self, arguments: &[Expression], ty: &Type) -> fmt::Result {
+        self.uses_errors = true;
+        let (function, arguments) = arguments.split_first().expect("sema checked that 'pcall' has a function");
+        let ExpressionKind::Identifier(name) = &function.kind else { unreachable!("sema only accepts named functions for 'pcall'") };
+        let mut args = Vec::new();
+        for arg in arguments {
+            let value = self.expression_to_string(arg)?;
+            if matches!(arg.kind, ExpressionKind::Identifier(_) | ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_) | ExpressionKind::Boolean(_)) {
+                args.push(value);
+                continue;
+            }
+            let temp = self.fresh_name("arg");
+            let c_type = self.c_type(&arg.ty);
+            let outer = mem::take(&mut self.output);
+            self.indent()?;
+            writeln!(&mut self.output, "{} {} = {};", c_type, temp, value)?;
+            let code = mem::replace(&mut self.output, outer);
+            self.hoisted.push_str(&code);
+            args.push(temp);
+        }
+        let call = format!("{}({})", self.resolve(name), args.join(", "));
+        let Type::Result { ok, .. } = ty else { unreachable!("sema types 'pcall' as a result") };
+        let c_type = self.c_type(ty);
+        let (temp, jump, outer_handler) = (self.fresh_name("pcall"), self.fresh_name("jump"), self.fresh_name("handler"));
+        let outer = mem::take(&mut self.output);
+        self.indent()?;
+        writeln!(&mut self.output, "{} {};", c_type, temp)?;
+        self.indent()?;
+        writeln!(&mut self.output, "jmp_buf {}, *{} = kita_handler;", jump, outer_handler)?;
+        self.indent()?;
+        writeln!(&mut self.output, "kita_handler = &{};", jump)?;
+        self.indent()?;
+        writeln!(&mut self.output, "if (setjmp({}) == 0) {{", jump)?;
+        self.indent()?;
+        if **ok == Type::Void {
+            writeln!(&mut self.output, "    {};", call)?;
+            self.indent()?;
+            writeln!(&mut self.output, "    {} = (({}){{ .ok = true }});", temp, c_type)?;
+        } else {
+            writeln!(&mut self.output, "    {} = (({}){{ .ok = true, .val = {} }});", temp, c_type, call)?;
+        }
+        self.indent()?;
+        writeln!(&mut self.output, "}} else {{")?;
+        self.indent()?;
+        writeln!(&mut self.output, "    {} = (({}){{ .ok = false, .err = kita_string_alloc(kita_error_message, (int64_t)strlen(kita_error_message), \"\", 0) }});", temp, c_type)?;
+        self.indent()?;
+        writeln!(&mut self.output, "}}")?;
+        self.indent()?;
+        writeln!(&mut self.output, "kita_handler = {};", outer_handler)?;
+        let code = mem::replace(&mut self.output, outer);
+        self.hoisted.push_str(&code);
+        write!(&mut self.output, "{}", temp)
+    }
+
+    /// Writes element `index` of the array `target` as a C lvalue, located by
+    /// the runtime function `func` (`kita_at` or `kita_slot`).
+    fn transpile_element(&mut self, func: &str, target: &Expression, index: &Expression, elem: &Type, span: &Span) -> fmt::Result {
+        let c_type = self.c_type(elem);
+        let [target, index] = <[String; 2]>::try_from(self.ordered_operands(&[target, index])?).expect("two operands");
+        write!(&mut self.output, "(*({} *){}({}, (int64_t)({}), sizeof({}), {}))", c_type, func, target, index, c_type, Self::c_location(span))
+    }
+
+    /// The source location of `span` as a C string, for runtime error messages.
+    fn c_location(span: &Span) -> String {
+        Self::c_string_literal(&format!("{}:{}:{}", span.file, span.line, span.col))
+    }
+
+    fn transpile_print(&mut self, arg: &Expression) -> fmt::Result {
+        match arg.ty {
+            Type::String => {
+                write!(&mut self.output, "kita_print_string(")?;
+                self.transpile_expression(arg)?;
+                write!(&mut self.output, ")")
+            }
+            Type::Bool => {
+                write!(&mut self.output, "puts(")?;
+                self.transpile_expression(arg)?;
+                write!(&mut self.output, " ? \"true\" : \"false\")")
+            }
+            Type::Float(_) => {
+                self.uses_floats = true;
+                write!(&mut self.output, "kita_print_float(")?;
+                self.transpile_expression(arg)?;
+                write!(&mut self.output, ")")
+            }
+            Type::Int(int) if !int.is_signed() => {
+                write!(&mut self.output, "printf(\"%llu\\n\", (unsigned long long)")?;
+                self.transpile_expression(arg)?;
+                write!(&mut self.output, ")")
+            }
+            _ => {
+                write!(&mut self.output, "printf(\"%lld\\n\", (long long)")?;
+                self.transpile_expression(arg)?;
+                write!(&mut self.output, ")")
+            }
+        }
+    }
+
+    /// C computes with integers narrower than `int` after promoting them to
+    /// `int`; a cast brings the result back to the Kita type so that it wraps
+    /// around as expected.
+    fn narrow_cast(ty: &Type) -> String {
+        match ty {
+            Type::Int(int) if int.bits() < 32 => format!("({})", Self::c_int_type(*int)),
+            _ => String::new(),
+        }
+    }
+
+    /// A cast from the 64-bit types the runtime's arithmetic helpers work in
+    /// to `ty`, if it is narrower.
+    fn exact_cast(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int(IntType::I64 | IntType::U64) | Type::Float(FloatType::F64) => String::new(),
+            ty => format!("({})", self.c_type(ty)),
+        }
+    }
+
+    /// A checked integer `left / right` of type `ty`.
+    fn int_division(&mut self, ty: &Type, left: &str, right: &str) -> String {
+        self.uses_math = true;
+        let func = if matches!(ty, Type::Int(int) if !int.is_signed()) { "kita_unsigned_int_div" } else { "kita_int_div" };
+        format!("({}{}({}, {}))", self.exact_cast(ty), func, left, right)
+    }
+
+    /// Operands of `..` may be numbers, which Lua converts to strings.
+    fn string_operand(ty: &Type, value: String) -> String {
+        let convert = match ty {
+            Type::String => return value,
+            Type::Float(_) => "kita_float_to_string",
+            Type::Int(int) if !int.is_signed() => "kita_uint_to_string",
+            _ => "kita_int_to_string",
+        };
+        format!("{}({})", convert, value)
+    }
+
     fn op_to_c(op: &Token) -> &str {
         match op {
             Token::Plus => "+", Token::Minus => "-", Token::Asterisk => "*",
             Token::Slash => "/", Token::Eq => "==", Token::NotEq => "!=",
-            Token::Lt => "<", Token::Gt => ">",
+            Token::Lt => "<", Token::Gt => ">", Token::LtEq => "<=", Token::GtEq => ">=",
+            Token::Ampersand => "&", Token::Pipe => "|",
+            Token::And => "&&", Token::Or => "||", Token::Not => "!", Token::Tilde => "~",
             _ => "/* unhandled op */"
         }
     }
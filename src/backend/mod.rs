@@ -1 +1,2 @@
 pub mod codegen_c;
+pub mod runtime;
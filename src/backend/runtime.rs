@@ -0,0 +1,279 @@
+//! C support code that the transpiler pastes into generated programs that need it.
+
+/// Number formatting shared by `print` and string conversion. Floats are
+/// printed like Lua does: 14 significant digits, and always recognizable as a
+/// float (`1.0` rather than `1`).
+pub const NUMBERS: &str = r#"#include <string.h>
+
+static int kita_format_float(char *buf, size_t size, double value) {
+    int len = snprintf(buf, size, "%.14g", value);
+    if (strspn(buf, "-0123456789") == (size_t)len && len + 2 < (int)size) {
+        buf[len++] = '.';
+        buf[len++] = '0';
+        buf[len] = '\0';
+    }
+    return len;
+}
+
+static void kita_print_float(double value) {
+    char buf[64];
+    kita_format_float(buf, sizeof buf, value);
+    puts(buf);
+}
+"#;
+
+/// Runtime errors. Inside a `pcall`, which points `kita_handler` at its jump
+/// buffer, an error jumps back to the `pcall` with its message in
+/// `kita_error_message`; anywhere else it ends the program.
+pub const ERRORS: &str = r#"#include <setjmp.h>
+#include <stdlib.h>
+
+static jmp_buf *kita_handler;
+static char kita_error_message[256];
+
+/* `where` is the source location of the error, or NULL if it is unknown. */
+static void kita_raise(const char *where, const char *message) {
+    if (!kita_handler) {
+        if (where) fprintf(stderr, "%s: ", where);
+        fprintf(stderr, "error: %s\n", message);
+        exit(1);
+    }
+    if (where) snprintf(kita_error_message, sizeof kita_error_message, "%s: %s", where, message);
+    else snprintf(kita_error_message, sizeof kita_error_message, "%s", message);
+    longjmp(*kita_handler, 1);
+}
+"#;
+
+/// Arithmetic with Lua semantics where C differs: floor division and modulo
+/// round towards negative infinity, and shifts by any amount are defined.
+/// Integer division by zero raises an error instead of trapping.
+pub const MATH: &str = r#"#include <math.h>
+#include <stdlib.h>
+
+static void kita_arith_error(const char *message) {
+    kita_raise(NULL, message);
+}
+
+/* `/` on integers truncates towards zero, as in C, and wraps on
+   INT64_MIN / -1. */
+static int64_t kita_int_div(int64_t a, int64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n/0'");
+    if (b == -1) return (int64_t)(0u - (uint64_t)a);
+    return a / b;
+}
+
+static uint64_t kita_unsigned_int_div(uint64_t a, uint64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n/0'");
+    return a / b;
+}
+
+static int64_t kita_floor_div(int64_t a, int64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n//0'");
+    if (b == -1) return (int64_t)(0u - (uint64_t)a);
+    int64_t q = a / b;
+    if (a % b != 0 && (a ^ b) < 0) q -= 1;
+    return q;
+}
+
+static int64_t kita_mod(int64_t a, int64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n%0'");
+    if (b == -1) return 0;
+    int64_t m = a % b;
+    if (m != 0 && (m ^ b) < 0) m += b;
+    return m;
+}
+
+static uint64_t kita_unsigned_div(uint64_t a, uint64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n//0'");
+    return a / b;
+}
+
+static uint64_t kita_unsigned_mod(uint64_t a, uint64_t b) {
+    if (b == 0) kita_arith_error("attempt to perform 'n%0'");
+    return a % b;
+}
+
+static double kita_float_mod(double a, double b) {
+    double m = fmod(a, b);
+    if ((m > 0) ? b < 0 : (m < 0 && b != m)) m += b;
+    return m;
+}
+
+/* Shifts the low `bits` bits of `a`. Negative counts shift the other way,
+   counts of `bits` or more give 0, and right shifts fill with zeros. */
+static uint64_t kita_shift_left(uint64_t a, int64_t n, int bits) {
+    if (bits < 64) a &= (UINT64_C(1) << bits) - 1;
+    if (n <= -bits || n >= bits) return 0;
+    return n >= 0 ? a << n : a >> -n;
+}
+"#;
+
+/// Kita strings are immutable, length-prefixed byte slices. Literals point at
+/// static storage; every other string (concatenation results, converted
+/// numbers) owns a fresh heap buffer. Kita has no garbage collector, so those
+/// buffers live until the program exits.
+pub const STRINGS: &str = r#"#include <ctype.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef struct { const char *data; int64_t len; } kita_string;
+
+static kita_string kita_str(const char *data, int64_t len) {
+    kita_string s = { data, len };
+    return s;
+}
+
+static kita_string kita_string_alloc(const char *a, int64_t a_len, const char *b, int64_t b_len) {
+    char *data = malloc((size_t)(a_len + b_len + 1));
+    if (!data) { fputs("kita: out of memory\n", stderr); exit(1); }
+    memcpy(data, a, (size_t)a_len);
+    memcpy(data + a_len, b, (size_t)b_len);
+    data[a_len + b_len] = '\0';
+    return kita_str(data, a_len + b_len);
+}
+
+static kita_string kita_concat(kita_string a, kita_string b) {
+    return kita_string_alloc(a.data, a.len, b.data, b.len);
+}
+
+/* Orders strings bytewise, like memcmp; a prefix sorts first. */
+static int kita_compare(kita_string a, kita_string b) {
+    int64_t n = a.len < b.len ? a.len : b.len;
+    int c = n ? memcmp(a.data, b.data, (size_t)n) : 0;
+    if (c != 0) return c;
+    return (a.len > b.len) - (a.len < b.len);
+}
+
+static kita_string kita_int_to_string(int64_t value) {
+    char buf[32];
+    int len = snprintf(buf, sizeof buf, "%lld", (long long)value);
+    return kita_string_alloc(buf, len, "", 0);
+}
+
+static kita_string kita_uint_to_string(uint64_t value) {
+    char buf[32];
+    int len = snprintf(buf, sizeof buf, "%llu", (unsigned long long)value);
+    return kita_string_alloc(buf, len, "", 0);
+}
+
+static kita_string kita_float_to_string(double value) {
+    char buf[64];
+    int len = kita_format_float(buf, sizeof buf, value);
+    return kita_string_alloc(buf, len, "", 0);
+}
+
+static kita_string kita_string_upper(kita_string s) {
+    kita_string r = kita_string_alloc(s.data, s.len, "", 0);
+    for (int64_t i = 0; i < r.len; i++) ((char *)r.data)[i] = (char)toupper((unsigned char)r.data[i]);
+    return r;
+}
+
+static kita_string kita_string_lower(kita_string s) {
+    kita_string r = kita_string_alloc(s.data, s.len, "", 0);
+    for (int64_t i = 0; i < r.len; i++) ((char *)r.data)[i] = (char)tolower((unsigned char)r.data[i]);
+    return r;
+}
+
+/* Bytes `i` through `j` of `s`, where negative positions count from the end
+   as in Lua's `string.sub`. The result shares the bytes of `s`. */
+static kita_string kita_string_sub(kita_string s, int64_t i, int64_t j) {
+    if (i < 0) i = i < -s.len ? 1 : s.len + i + 1; else if (i == 0) i = 1;
+    if (j < 0) j = j < -s.len ? 0 : s.len + j + 1; else if (j > s.len) j = s.len;
+    if (i > j) return kita_str("", 0);
+    return kita_str(s.data + i - 1, j - i + 1);
+}
+
+static void kita_print_string(kita_string s) {
+    fwrite(s.data, 1, (size_t)s.len, stdout);
+    putchar('\n');
+}
+"#;
+
+/// Arrays and slices are references to a shared `kita_array` header. Indices
+/// count from 1 and are checked unless `KITA_UNCHECKED` is defined, in which
+/// case an out-of-bounds access is undefined behavior as in C. A growable
+/// array that outgrows its buffer moves to a bigger one; as with strings, the
+/// old buffer is never freed, so slices taken before keep pointing at it.
+pub const ARRAYS: &str = r#"#include <stdlib.h>
+#include <string.h>
+
+typedef struct { char *data; int64_t len, cap; bool growable; } kita_array;
+
+static void *kita_array_alloc(size_t size) {
+    void *p = malloc(size ? size : 1);
+    if (!p) { fputs("kita: out of memory\n", stderr); exit(1); }
+    return p;
+}
+
+static kita_array *kita_array_new(int64_t len, size_t size, const void *items, bool growable) {
+    kita_array *a = kita_array_alloc(sizeof *a);
+    a->data = kita_array_alloc((size_t)len * size);
+    if (len) memcpy(a->data, items, (size_t)len * size);
+    a->len = a->cap = len;
+    a->growable = growable;
+    return a;
+}
+
+static void kita_index_error(const char *where, int64_t i, int64_t len) {
+    char message[128];
+    snprintf(message, sizeof message, "index %lld out of bounds for an array of length %lld", (long long)i, (long long)len);
+    kita_raise(where, message);
+}
+
+/* The address of element `i` of `a`. */
+static void *kita_at(kita_array *a, int64_t i, size_t size, const char *where) {
+#ifndef KITA_UNCHECKED
+    if (i < 1 || i > a->len) kita_index_error(where, i, a->len);
+#endif
+    (void)where;
+    return a->data + (size_t)(i - 1) * size;
+}
+
+/* Like kita_at, for assigning to the element. The element just past the end
+   of a growable array may be assigned too, which appends it, as Lua's
+   `t[#t + 1] = v` does. */
+static void *kita_slot(kita_array *a, int64_t i, size_t size, const char *where) {
+    if (i == a->len + 1 && a->growable) {
+        if (a->len == a->cap) {
+            int64_t cap = a->cap ? a->cap * 2 : 4;
+            char *data = kita_array_alloc((size_t)cap * size);
+            memcpy(data, a->data, (size_t)a->len * size);
+            a->data = data;
+            a->cap = cap;
+        }
+        a->len++;
+    }
+    return kita_at(a, i, size, where);
+}
+
+/* Elements `i` through `j` of `a`, sharing its storage. */
+static kita_array *kita_slice(kita_array *a, int64_t i, int64_t j, size_t size, const char *where) {
+#ifndef KITA_UNCHECKED
+    if (i < 1 || i > a->len + 1) kita_index_error(where, i, a->len);
+    if (j < i - 1 || j > a->len) kita_index_error(where, j, a->len);
+#endif
+    (void)where;
+    kita_array *s = kita_array_alloc(sizeof *s);
+    s->data = a->data + (size_t)(i - 1) * size;
+    s->len = s->cap = j - i + 1;
+    s->growable = false;
+    return s;
+}
+"#;
+
+pub const RECORDS: &str = r#"#include <stdlib.h>
+#include <string.h>
+
+/* Copies a record's initial field values to a new heap allocation. */
+static void *kita_record_new(const void *init, size_t size) {
+    void *p = malloc(size);
+    if (!p) { fputs("kita: out of memory\n", stderr); exit(1); }
+    return memcpy(p, init, size);
+}
+"#;
+
+/// A function value: a C function, and the environment holding the variables
+/// its closure captured, which the function receives as its first argument.
+/// The function is called through a pointer cast back to its actual type.
+pub const CLOSURES: &str = r#"typedef struct { void (*fn)(void); void *env; } kita_closure;
+"#;
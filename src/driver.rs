@@ -0,0 +1,180 @@
+//! The `kita build` command line, shared by the `kita` and `kita-bin`
+//! binaries so both accept the same flags.
+
+use clap::{Parser, Subcommand, ValueEnum};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command as OsCommand, Stdio};
+
+use crate::backend::codegen_c::CTranspiler;
+use crate::frontend::{diagnostic::Severity, lexer::Lexer, monomorphize::monomorphize, parser::Parser as KitaParser, sema::SemanticAnalyzer};
+
+#[derive(Parser, Debug)]
+#[command(version, author, about = "The Kita Programming Language Compiler")]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Builds a .ki file into a native executable by transpiling to C
+    Build {
+        file: PathBuf,
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Keep the intermediate C source file for debugging
+        #[arg(short, long, name = "save-c")]
+        save_c_source: bool,
+        /// Explicitly specify the C compiler to use (e.g., 'gcc', 'clang', 'cl.exe')
+        #[arg(long, name = "c-compiler")]
+        c_compiler: Option<String>,
+        /// How to treat warnings; `-W error` makes them fail the build
+        #[arg(short = 'W', value_enum, default_value_t = Warnings::Warn)]
+        warnings: Warnings,
+        /// Leave out the bounds checks on array indexing
+        #[arg(long, name = "release-unchecked")]
+        release_unchecked: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Warnings {
+    /// Report warnings and continue
+    Warn,
+    /// Treat warnings as errors
+    Error,
+}
+
+fn is_compiler_available(name: &str) -> bool {
+    OsCommand::new(name)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+fn get_c_compiler_name(cli_arg: &Option<String>) -> String {
+    if let Some(compiler) = cli_arg {
+        return compiler.clone();
+    }
+    if let Ok(compiler) = env::var("KITA_CC") {
+        return compiler;
+    }
+
+    if cfg!(target_os = "windows") {
+        const WINDOWS_CANDIDATES: &[&str] = &["cl.exe", "clang.exe", "gcc.exe"];
+        for &compiler in WINDOWS_CANDIDATES {
+            if is_compiler_available(compiler) {
+                return compiler.to_string();
+            }
+        }
+        "cl.exe".to_string()
+    } else {
+        const UNIX_CANDIDATES: &[&str] = &["gcc", "clang"];
+        for &compiler in UNIX_CANDIDATES {
+            if is_compiler_available(compiler) {
+                return compiler.to_string();
+            }
+        }
+        "gcc".to_string()
+    }
+}
+
+/// Parses the command line and runs the requested command.
+pub fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Commands::Build { file, output, save_c_source, c_compiler, warnings, release_unchecked } => {
+            build_file(file, output, save_c_source, &c_compiler, warnings, release_unchecked)
+        }
+    }
+}
+
+/// Reports a build failure and exits with a failing status.
+fn fail(message: &str) -> ! {
+    eprintln!("\n{}", message);
+    process::exit(1);
+}
+
+fn build_file(
+    path: PathBuf,
+    output_path: Option<PathBuf>,
+    save_c_source: bool,
+    c_compiler_flag: &Option<String>,
+    warnings: Warnings,
+    release_unchecked: bool,
+) {
+    println!("[1/4] Reading Source: {:?}", path);
+    let source_code = fs::read_to_string(&path).unwrap_or_else(|e| fail(&format!("Failed to read {:?}: {}", path, e)));
+
+    println!("[2/4] Frontend Analysis...");
+    let lexer = Lexer::with_file(source_code.clone(), &path.display().to_string());
+    let mut parser = KitaParser::new(lexer);
+    let mut program = parser.parse_program();
+    if !parser.errors.is_empty() {
+        eprintln!("\nParsing failed with {} errors:", parser.errors.len());
+        for err in parser.errors {
+            eprintln!("\n{}", err.render(&source_code));
+        }
+        process::exit(1);
+    }
+    let mut diagnostics = SemanticAnalyzer::new().analyze(&mut program);
+    if !diagnostics.iter().any(|d| d.is_error()) { diagnostics.extend(monomorphize(&mut program)); }
+    if warnings == Warnings::Error {
+        for diagnostic in &mut diagnostics { diagnostic.severity = Severity::Error; }
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("\n{}", diagnostic.render(&source_code));
+    }
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+    if errors > 0 {
+        eprintln!("\nSemantic analysis failed with {} errors", errors);
+        process::exit(1);
+    }
+    println!("     ...Frontend analysis successful.");
+
+    println!("[3/4] Backend C Transpilation...");
+    let mut transpiler = CTranspiler::new().with_bounds_checks(!release_unchecked);
+    let c_code = transpiler.transpile(program).expect("Failed to transpile to C");
+    
+    let output_file = output_path.unwrap_or_else(|| {
+        let mut new_path = path.clone();
+        new_path.set_extension("");
+        if cfg!(target_os = "windows") {
+            new_path.set_extension("exe");
+        }
+        new_path
+    });
+
+    let c_file_path = output_file.with_extension("c");
+    fs::write(&c_file_path, &c_code).unwrap_or_else(|e| fail(&format!("Failed to write C source file: {}", e)));
+    println!("     ...Generated C code at: {:?}", c_file_path);
+
+    let compiler_name = get_c_compiler_name(c_compiler_flag);
+    println!("[4/4] Compiling C code with: {}...", compiler_name);
+
+    let mut command = OsCommand::new(&compiler_name);
+    if compiler_name == "cl.exe" {
+        command.arg("/nologo").arg("/O2").arg("/Fe:").arg(&output_file);
+    } else {
+        command.arg("-O2").arg("-o").arg(&output_file);
+    }
+    command.arg(&c_file_path);
+    // The runtime uses <math.h>, which lives in a separate library on Unix.
+    if compiler_name != "cl.exe" { command.arg("-lm"); }
+    
+    let status = command.status().unwrap_or_else(|_| fail(&format!("Failed to execute C compiler '{}'. Is it in your PATH?", compiler_name)));
+    
+    if status.success() {
+        println!("\n>>> Successfully built executable: {:?}", output_file);
+        if !save_c_source {
+            fs::remove_file(c_file_path).unwrap();
+        }
+    } else {
+        eprintln!("\nC compilation failed. The intermediate C file was saved for debugging: {:?}", c_file_path);
+        process::exit(1);
+    }
+}
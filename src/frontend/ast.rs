@@ -1,22 +1,215 @@
-use super::token::Token;
+use super::{span::Span, token::Token, types::Type};
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Statement {
-    Let { name: String, value: Expression },
-    Return(Expression),
+pub struct Statement {
+    pub kind: StatementKind, pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum StatementKind {
+    /// `local name = value`, or with several variables, `local a, b = x, y`,
+    /// one value for each, or `local a, b = f()`, which takes apart the values
+    /// `f` returns. A variable named `_` there discards its value.
+    Let { vars: Vec<Variable>, values: Vec<Expression>, is_const: bool },
+    /// `target = value`, or `target op= value` when `op` is set.
+    Assign { target: Box<Expression>, op: Option<Token>, value: Expression },
+    /// `return value, ...`; more than one value makes a `Type::Tuple`.
+    Return(Vec<Expression>),
     Expression(Expression),
+    /// `ret` is the return type, annotated or inferred; filled in by semantic analysis.
+    /// A method `function Type:name(...)` has a `receiver`, and as in Lua its
+    /// first parameter is the implicit `self`. A generic function
+    /// `function name<T>(...)` has `type_params`.
+    Function { name: String, type_params: Vec<TypeParam>, receiver: Option<String>, params: Vec<Param>, return_annotation: Option<TypeExpr>, body: BlockStatement, ret: Type },
+    While { condition: Expression, body: BlockStatement },
+    NumericFor { var: String, start: Box<Expression>, stop: Box<Expression>, step: Option<Box<Expression>>, body: BlockStatement },
+    /// `for index, value in iterable do ... end`; the value variable is optional.
+    ForIn { index: String, value: Option<String>, iterable: Expression, body: BlockStatement },
+    Repeat { body: BlockStatement, condition: Expression },
+    /// `record Name field: type, ... end`, or generic, `record Name<T, ...> ... end`.
+    /// A record implementing interfaces names them: `record Name: Shape, ...`.
+    Record { name: String, type_params: Vec<TypeParam>, interfaces: Vec<TypeExpr>, fields: Vec<Field> },
+    /// `enum Name Variant(type, ...) | ... end`, or `enum Name: Shape, ...`.
+    Enum { name: String, interfaces: Vec<TypeExpr>, variants: Vec<Variant> },
+    /// `interface Name function method(self, ...): type ... end`
+    Interface { name: String, methods: Vec<InterfaceMethod> },
+    Break,
+    Continue,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind, pub span: Span,
+    /// Filled in by semantic analysis; `Type::Unknown` straight out of the parser.
+    pub ty: Type,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self { Self { kind, span, ty: Type::Unknown } }
 }
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expression {
+pub enum ExpressionKind {
     Identifier(String),
-    IntegerLiteral(i64),
+    IntegerLiteral(i128),
+    FloatLiteral(f64),
+    StringLiteral(String),
     Boolean(bool),
+    Nil,
     Prefix { op: Token, right: Box<Expression> },
     Infix { op: Token, left: Box<Expression>, right: Box<Expression> },
     If { condition: Box<Expression>, consequence: BlockStatement, alternative: Option<BlockStatement> },
-    FunctionLiteral { params: Vec<String>, body: BlockStatement },
+    /// `function(params) ... end` as a value. `captures` are the variables of
+    /// enclosing functions the body refers to, and `escapes` is set unless the
+    /// closure is known not to outlive the block it is created in. Both are
+    /// filled in by semantic analysis.
+    FunctionLiteral { params: Vec<Param>, return_annotation: Option<TypeExpr>, body: BlockStatement, captures: Vec<Capture>, escapes: bool },
     Call { function: Box<Expression>, arguments: Vec<Expression> },
+    /// `receiver:method(arguments)`
+    MethodCall { receiver: Box<Expression>, method: String, arguments: Vec<Expression> },
+    /// `{a, b, c}`
+    ArrayLiteral(Vec<Expression>),
+    /// `target[index]`
+    Index { target: Box<Expression>, index: Box<Expression> },
+    /// `target[start..stop]`, the elements `start` through `stop`.
+    Slice { target: Box<Expression>, start: Box<Expression>, stop: Box<Expression> },
+    /// `Name { field = value, ... }`
+    RecordLiteral { name: String, fields: Vec<FieldInit> },
+    /// `target.name`
+    Field { target: Box<Expression>, name: String },
+    /// `match subject with | pattern -> ... end`
+    Match { subject: Box<Expression>, arms: Vec<MatchArm> },
+    /// `value?`: the value of a successful `Result`. A failed one is returned
+    /// from the enclosing function.
+    Try(Box<Expression>),
+    /// A value used where a value of its optional type `ty`, or a successful
+    /// `Result` of `ty`, is expected. Inserted by semantic analysis; never
+    /// produced by the parser.
+    Wrap(Box<Expression>),
+    /// The value of a variable of optional type that semantic analysis has
+    /// proved is not nil here. Inserted by semantic analysis as well.
+    Unwrap(Box<Expression>),
+    /// The generic function `name` with `type_args` for its type parameters.
+    /// Inserted by semantic analysis in place of the identifier naming it;
+    /// monomorphization names the function's copy for those types instead.
+    Instance { name: String, type_args: Vec<Type> },
+    /// A record or enum value used where a `dyn Interface` of an interface
+    /// its type implements is expected. Inserted by semantic analysis.
+    Dyn(Box<Expression>),
+}
+
+/// A type as written in the source, such as the `i32` in `local x: i32 = 5`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeExpr {
+    pub kind: TypeExprKind, pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum TypeExprKind {
+    Named(String),
+    /// `{T}`, or `{T; N}` with a fixed size.
+    Array { elem: Box<TypeExpr>, size: Option<u64> },
+    /// `[T]`
+    Slice(Box<TypeExpr>),
+    /// `T?`
+    Optional(Box<TypeExpr>),
+    /// `Name<T, ...>`
+    Generic { name: String, args: Vec<TypeExpr> },
+    /// `function(T, ...): R`, or without a return type, `function(T, ...)`.
+    Function { params: Vec<TypeExpr>, ret: Option<Box<TypeExpr>> },
+    /// `(T, U, ...)`, the return type of a function returning several values.
+    Tuple(Vec<TypeExpr>),
+    /// `dyn Interface`
+    Dyn(String),
+}
+
+/// A type parameter of a generic declaration, such as the `T: Number` in
+/// `function max<T: Number>(a: T, b: T): T`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeParam {
+    pub name: String, pub bound: Option<String>, pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Param {
+    pub name: String, pub annotation: Option<TypeExpr>, pub span: Span,
+    /// Filled in by semantic analysis, like the `shared` of `StatementKind::Let`.
+    pub ty: Type, pub shared: bool,
+}
+
+/// A variable declared by `local` or `const`, as the `x: i64` in `local x: i64 = 5`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variable {
+    pub name: String, pub annotation: Option<TypeExpr>, pub span: Span,
+    /// The declared type of the variable: the annotation if there is one,
+    /// otherwise the type of its value. Filled in by semantic analysis, as is
+    /// `shared`, set if a closure that may outlive the variable refers to it.
+    pub ty: Type, pub shared: bool,
+}
+
+/// A variable of an enclosing function that a function literal refers to.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Capture {
+    pub name: String, pub ty: Type,
+    /// Whether the closure shares the variable rather than holding a copy of
+    /// it, because the variable is assigned to.
+    pub by_reference: bool,
+}
+
+/// A field in a record declaration.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Field {
+    pub name: String, pub annotation: TypeExpr, pub span: Span,
+    /// Filled in by semantic analysis.
+    pub ty: Type,
+}
+
+/// A `name = value` pair in a record constructor.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldInit {
+    pub name: String, pub span: Span, pub value: Expression,
+}
+
+/// A method of an interface declaration. `self` is not among its `params`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct InterfaceMethod {
+    pub name: String, pub params: Vec<Param>, pub return_annotation: Option<TypeExpr>, pub span: Span,
+    /// The method's signature, without `self`; filled in by semantic analysis.
+    pub ty: Type,
+}
+
+/// A variant of an enum declaration, with the types of its fields.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+    pub name: String, pub fields: Vec<TypeExpr>, pub span: Span,
+    /// Filled in by semantic analysis.
+    pub field_types: Vec<Type>,
+}
+
+/// `| pattern [if guard] -> body` in a `match`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern, pub guard: Option<Expression>, pub body: BlockStatement, pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pattern {
+    pub kind: PatternKind, pub span: Span,
+    /// The type of the value matched against the pattern; filled in by semantic analysis.
+    pub ty: Type,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PatternKind {
+    /// `_`
+    Wildcard,
+    /// A name, bound to the matched value. Semantic analysis turns the name of
+    /// a variant without fields into a `Variant` pattern instead.
+    Binding(String),
+    /// A number, string or boolean literal.
+    Literal(Box<Expression>),
+    /// `Variant(pattern, ...)`
+    Variant { name: String, fields: Vec<Pattern> },
 }
 
 pub type Program = Vec<Statement>;
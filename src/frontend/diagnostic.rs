@@ -0,0 +1,54 @@
+use super::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity { Error, Warning }
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Severity::Error => "error", Severity::Warning => "warning" })
+    }
+}
+
+/// A compiler message tied to the piece of source code that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity, pub message: String, pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self { Self { severity: Severity::Error, message: message.into(), span } }
+    pub fn warning(message: impl Into<String>, span: Span) -> Self { Self { severity: Severity::Warning, message: message.into(), span } }
+
+    pub fn is_error(&self) -> bool { self.severity == Severity::Error }
+
+    /// Renders the diagnostic together with the offending source line and a caret
+    /// underline, e.g.
+    ///
+    /// ```text
+    /// error: Expected next token to be Then, got Int(1) instead
+    ///  --> main.ki:3:6
+    ///   |
+    /// 3 | if x 1 then
+    ///   |      ^
+    /// ```
+    pub fn render(&self, source: &str) -> String {
+        let line_text = source.lines().nth(self.span.line.saturating_sub(1)).unwrap_or("");
+        let gutter = " ".repeat(self.span.line.to_string().len());
+        // Keep tabs so the caret lines up with the source line as printed.
+        let padding: String = line_text.chars().take(self.span.col.saturating_sub(1)).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+        let remaining = line_text.chars().count().saturating_sub(self.span.col.saturating_sub(1));
+        let width = source.get(self.span.byte_range.clone()).map_or(1, |s| s.chars().count()).clamp(1, remaining.max(1));
+        format!(
+            "{}: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.severity, self.message, gutter, self.span.file, self.span.line, self.span.col,
+            gutter, self.span.line, line_text, gutter, padding, "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}: {}", self.span.file, self.span.line, self.span.col, self.severity, self.message)
+    }
+}
@@ -0,0 +1,163 @@
+use super::{span::Span, types::Type};
+
+/// The state of one inference variable (`Type::Var`).
+#[derive(Debug, Clone)]
+enum TypeVar {
+    /// Not determined yet. `numeric` records where the variable was first used
+    /// in arithmetic, after which it may only be bound to a number type, and
+    /// `ordered` where it was first ordered, after which it may only be bound
+    /// to a number or a string.
+    Open { numeric: Option<Span>, ordered: Option<Span> },
+    /// Determined by the expression at `span`.
+    Bound { ty: Type, span: Span },
+}
+
+/// Unification of types containing inference variables, used to infer the
+/// parameter and return types of unannotated functions.
+#[derive(Debug, Default)]
+pub struct Inference {
+    vars: Vec<TypeVar>,
+}
+
+impl Inference {
+    pub fn fresh(&mut self) -> Type {
+        self.vars.push(TypeVar::Open { numeric: None, ordered: None });
+        Type::Var(self.vars.len() as u32 - 1)
+    }
+
+    /// Follows bound variables until reaching a type that is not a bound variable.
+    pub fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.vars[var as usize] {
+                TypeVar::Bound { ty: bound, .. } => ty = bound.clone(),
+                TypeVar::Open { .. } => break,
+            }
+        }
+        ty
+    }
+
+    /// Substitutes all bound variables in `ty`, leaving open ones in place.
+    pub fn resolve(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Function { params, ret } => Type::Function { params: params.iter().map(|p| self.resolve(p)).collect(), ret: Box::new(self.resolve(&ret)) },
+            Type::Array { elem, size } => Type::Array { elem: Box::new(self.resolve(&elem)), size },
+            Type::Slice(elem) => Type::Slice(Box::new(self.resolve(&elem))),
+            Type::Optional(inner) => Type::Optional(Box::new(self.resolve(&inner))),
+            Type::Result { ok, err } => Type::Result { ok: Box::new(self.resolve(&ok)), err: Box::new(self.resolve(&err)) },
+            Type::Tuple(elems) => Type::Tuple(elems.iter().map(|e| self.resolve(e)).collect()),
+            Type::Generic { name, args } => Type::Generic { name, args: args.iter().map(|a| self.resolve(a)).collect() },
+            ty => ty,
+        }
+    }
+
+    /// Like `resolve`, but replaces variables that nothing constrained with
+    /// `i64`, Kita's default number type.
+    pub fn finish(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Function { params, ret } => Type::Function { params: params.iter().map(|p| self.finish(p)).collect(), ret: Box::new(self.finish(&ret)) },
+            Type::Array { elem, size } => Type::Array { elem: Box::new(self.finish(&elem)), size },
+            Type::Slice(elem) => Type::Slice(Box::new(self.finish(&elem))),
+            Type::Optional(inner) => Type::Optional(Box::new(self.finish(&inner))),
+            Type::Result { ok, err } => Type::Result { ok: Box::new(self.finish(&ok)), err: Box::new(self.finish(&err)) },
+            Type::Tuple(elems) => Type::Tuple(elems.iter().map(|e| self.finish(e)).collect()),
+            Type::Generic { name, args } => Type::Generic { name, args: args.iter().map(|a| self.finish(a)).collect() },
+            Type::Var(_) => Type::I64,
+            ty => ty,
+        }
+    }
+
+    /// Whether `ty` is, or may still become, a number type. Open variables are
+    /// restricted to numbers from now on, because of the expression at `span`.
+    pub fn require_numeric(&mut self, ty: &Type, span: &Span) -> bool {
+        match self.shallow(ty) {
+            Type::Var(var) => {
+                if let TypeVar::Open { numeric: numeric @ None, .. } = &mut self.vars[var as usize] { *numeric = Some(span.clone()); }
+                true
+            }
+            ty => ty.is_numeric(),
+        }
+    }
+
+    /// Whether `ty` can be, or may still become, a type whose values can be
+    /// ordered. Open variables are restricted to such types from now on,
+    /// because of the expression at `span`.
+    pub fn require_ordered(&mut self, ty: &Type, span: &Span) -> bool {
+        match self.shallow(ty) {
+            Type::Var(var) => {
+                if let TypeVar::Open { ordered: ordered @ None, .. } = &mut self.vars[var as usize] { *ordered = Some(span.clone()); }
+                true
+            }
+            ty => ty.is_ordered(),
+        }
+    }
+
+    pub fn is_open(&self, ty: &Type) -> bool { matches!(self.shallow(ty), Type::Var(_)) }
+
+    /// Makes `a` and `b` the same type, binding variables as needed. `span` is
+    /// remembered as the place that determined the variables bound here.
+    pub fn unify(&mut self, a: &Type, b: &Type, span: &Span) -> bool {
+        match (self.shallow(a), self.shallow(b)) {
+            (Type::Unknown | Type::Error, _) | (_, Type::Unknown | Type::Error) => true,
+            (Type::Var(a), Type::Var(b)) if a == b => true,
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => self.bind(var, ty, span),
+            (Type::Function { params: a_params, ret: a_ret }, Type::Function { params: b_params, ret: b_ret }) => {
+                a_params.len() == b_params.len()
+                    && a_params.iter().zip(&b_params).all(|(a, b)| self.unify(a, b, span))
+                    && self.unify(&a_ret, &b_ret, span)
+            }
+            (Type::Array { elem: a, size: a_size }, Type::Array { elem: b, size: b_size }) => a_size == b_size && self.unify(&a, &b, span),
+            (Type::Slice(a), Type::Slice(b)) | (Type::Optional(a), Type::Optional(b)) => self.unify(&a, &b, span),
+            (Type::Result { ok: a_ok, err: a_err }, Type::Result { ok: b_ok, err: b_err }) => self.unify(&a_ok, &b_ok, span) && self.unify(&a_err, &b_err, span),
+            (Type::Tuple(a), Type::Tuple(b)) => a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| self.unify(a, b, span)),
+            (Type::Generic { name: a_name, args: a }, Type::Generic { name: b_name, args: b }) => {
+                a_name == b_name && a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| self.unify(a, b, span))
+            }
+            (a, b) => a == b,
+        }
+    }
+
+    fn bind(&mut self, var: u32, ty: Type, span: &Span) -> bool {
+        if self.occurs(var, &ty) { return false; }
+        let TypeVar::Open { numeric, ordered } = self.vars[var as usize].clone() else { unreachable!("only open variables are bound") };
+        if numeric.is_some_and(|at| !self.require_numeric(&ty, &at)) { return false; }
+        if ordered.is_some_and(|at| !self.require_ordered(&ty, &at)) { return false; }
+        self.vars[var as usize] = TypeVar::Bound { ty, span: span.clone() };
+        true
+    }
+
+    fn occurs(&self, var: u32, ty: &Type) -> bool {
+        match self.shallow(ty) {
+            Type::Var(other) => other == var,
+            Type::Function { params, ret } => params.iter().any(|p| self.occurs(var, p)) || self.occurs(var, &ret),
+            Type::Array { elem, .. } | Type::Slice(elem) | Type::Optional(elem) => self.occurs(var, &elem),
+            Type::Result { ok, err } => self.occurs(var, &ok) || self.occurs(var, &err),
+            Type::Tuple(elems) | Type::Generic { args: elems, .. } => elems.iter().any(|e| self.occurs(var, e)),
+            _ => false,
+        }
+    }
+
+    /// Where the type of `ty` was inferred, if it is a variable that was bound
+    /// or restricted to numbers or ordered types. A variable bound to another
+    /// is inferred where that one is.
+    pub fn inferred_at(&self, ty: &Type) -> Option<&Span> {
+        match ty {
+            Type::Var(var) => match &self.vars[*var as usize] {
+                TypeVar::Bound { ty: other @ Type::Var(_), span } => self.inferred_at(other).or(Some(span)),
+                TypeVar::Bound { span, .. } => Some(span),
+                TypeVar::Open { numeric, ordered } => numeric.as_ref().or(ordered.as_ref()),
+            },
+            _ => None,
+        }
+    }
+
+    /// Names `ty` for diagnostics, calling a variable restricted to numbers
+    /// "number" and one restricted to ordered types "number or string".
+    pub fn describe(&self, ty: &Type) -> String {
+        match self.shallow(ty) {
+            Type::Var(var) if matches!(self.vars[var as usize], TypeVar::Open { numeric: Some(_), .. }) => "number".to_string(),
+            Type::Var(var) if matches!(self.vars[var as usize], TypeVar::Open { ordered: Some(_), .. }) => "number or string".to_string(),
+            _ => self.resolve(ty).to_string(),
+        }
+    }
+}
@@ -1,50 +1,97 @@
-use super::token::{lookup_ident, Token};
+use super::span::Span;
+use super::token::{lookup_ident, SpannedToken, Token};
+use std::rc::Rc;
 
 pub struct Lexer {
     input: Vec<char>, position: usize, read_position: usize, ch: char,
+    // Byte offset of every char in `input`, plus one past the end.
+    byte_offsets: Vec<usize>, file: Rc<str>, line: usize, col: usize,
 }
 
 impl Lexer {
-    pub fn new(input: String) -> Self {
-        let mut l = Self { input: input.chars().collect(), position: 0, read_position: 0, ch: '\0' };
+    pub fn new(input: String) -> Self { Self::with_file(input, "<input>") }
+
+    pub fn with_file(input: String, file: &str) -> Self {
+        let byte_offsets = input.char_indices().map(|(i, _)| i).chain(std::iter::once(input.len())).collect();
+        let mut l = Self {
+            input: input.chars().collect(), position: 0, read_position: 0, ch: '\0',
+            byte_offsets, file: file.into(), line: 1, col: 0,
+        };
         l.read_char();
         l
     }
 
-    pub fn next_token(&mut self) -> Token {
+    pub fn next_token(&mut self) -> SpannedToken {
         self.skip_whitespace_and_comments();
+        let (start, line, col) = (self.position, self.line, self.col);
+        let token = self.read_token();
+        let byte_range = self.byte_offset(start)..self.byte_offset(self.position);
+        SpannedToken { token, span: Span { file: self.file.clone(), line, col, byte_range } }
+    }
+
+    fn read_token(&mut self) -> Token {
         let tok = match self.ch {
             '=' => if self.peek() == '=' { self.read_char(); Token::Eq } else { Token::Assign },
-            '~' | '!' => if self.peek() == '=' { self.read_char(); Token::NotEq } else { Token::Illegal(self.ch.to_string()) },
+            '~' | '!' if self.peek() == '=' => { self.read_char(); Token::NotEq }
+            '~' => Token::Tilde,
+            '+' | '-' | '*' | '/' if self.peek() == '=' => {
+                let tok = match self.ch { '+' => Token::PlusAssign, '-' => Token::MinusAssign, '*' => Token::AsteriskAssign, _ => Token::SlashAssign };
+                self.read_char();
+                tok
+            }
+            '/' if self.peek() == '/' => { self.read_char(); Token::FloorDiv }
+            '-' if self.peek() == '>' => { self.read_char(); Token::Arrow }
+            '<' | '>' if self.peek() == '=' || self.peek() == self.ch => {
+                let tok = match (self.ch, self.peek()) { ('<', '=') => Token::LtEq, ('>', '=') => Token::GtEq, ('<', _) => Token::ShiftLeft, _ => Token::ShiftRight };
+                self.read_char();
+                tok
+            }
             '+' => Token::Plus, '-' => Token::Minus, '/' => Token::Slash, '*' => Token::Asterisk,
-            '<' => Token::Lt, '>' => Token::Gt,
-            ',' => Token::Comma, '(' => Token::LParen, ')' => Token::RParen,
+            '<' => Token::Lt, '>' => Token::Gt, '#' => Token::Hash,
+            '%' => Token::Percent, '^' => Token::Caret, '&' => Token::Ampersand, '|' => Token::Pipe,
+            '.' if self.peek() == '.' => { self.read_char(); Token::Concat }
+            '.' if self.peek().is_ascii_digit() => return self.read_number(),
+            '.' => Token::Dot,
+            '"' | '\'' => return self.read_string(),
+            '[' if matches!(self.peek(), '[' | '=') => return self.read_long_string(),
+            ',' => Token::Comma, ':' => Token::Colon, '?' => Token::Question, ';' => Token::Semicolon, '(' => Token::LParen, ')' => Token::RParen,
+            '{' => Token::LBrace, '}' => Token::RBrace, '[' => Token::LBracket, ']' => Token::RBracket,
             '\0' => Token::Eof,
             _ => {
                 if self.ch.is_alphabetic() || self.ch == '_' {
                     let ident = self.read_identifier();
                     return lookup_ident(&ident);
-                } else if self.ch.is_digit(10) {
-                    return Token::Int(self.read_number());
+                } else if self.ch.is_ascii_digit() {
+                    return self.read_number();
                 } else { Token::Illegal(self.ch.to_string()) }
             }
         };
         self.read_char();
         tok
     }
-    
+
     fn read_char(&mut self) {
+        if self.ch == '\n' { self.line += 1; self.col = 1; } else { self.col += 1; }
         self.ch = if self.read_position >= self.input.len() { '\0' } else { self.input[self.read_position] };
         self.position = self.read_position;
         self.read_position += 1;
     }
 
     fn peek(&self) -> char { if self.read_position >= self.input.len() { '\0' } else { self.input[self.read_position] } }
-    
+
+    fn byte_offset(&self, position: usize) -> usize { self.byte_offsets[position.min(self.input.len())] }
+
     fn skip_whitespace_and_comments(&mut self) {
         loop {
             if self.ch.is_whitespace() { self.read_char(); continue; }
-            if self.ch == '-' && self.peek() == '-' { while self.ch != '\n' && self.ch != '\0' { self.read_char(); } continue; }
+            if self.ch == '-' && self.peek() == '-' {
+                self.read_char();
+                self.read_char();
+                // `--[[ ... ]]` comments run to the matching long bracket.
+                if self.ch == '[' && matches!(self.peek(), '[' | '=') && self.read_long_bracket().is_some() { continue; }
+                while self.ch != '\n' && self.ch != '\0' { self.read_char(); }
+                continue;
+            }
             break;
         }
     }
@@ -55,9 +102,143 @@ impl Lexer {
         self.input[pos..self.position].iter().collect()
     }
 
-    fn read_number(&mut self) -> i64 {
+    /// Reads a quoted string, translating Lua's escape sequences. On error the
+    /// rest of the literal is skipped and an `Illegal` token describes the problem.
+    fn read_string(&mut self) -> Token {
+        let quote = self.ch;
+        let mut value = String::new();
+        let mut error = None;
+        self.read_char();
+        loop {
+            match self.ch {
+                c if c == quote => { self.read_char(); break; }
+                '\0' | '\n' => return Token::Illegal("unterminated string literal".to_string()),
+                '\\' => {
+                    self.read_char();
+                    match self.read_escape() {
+                        Ok(Some(c)) => value.push(c),
+                        Ok(None) => {}
+                        Err(e) => { error.get_or_insert(e); }
+                    }
+                }
+                c => { value.push(c); self.read_char(); }
+            }
+        }
+        match error { Some(e) => Token::Illegal(e), None => Token::Str(value) }
+    }
+
+    /// Reads the escape sequence after a backslash. `\z` yields no character.
+    fn read_escape(&mut self) -> Result<Option<char>, String> {
+        let simple = match self.ch {
+            'n' => Some('\n'), 't' => Some('\t'), 'r' => Some('\r'), 'a' => Some('\x07'), 'b' => Some('\x08'),
+            'f' => Some('\x0c'), 'v' => Some('\x0b'), '\\' => Some('\\'), '"' => Some('"'), '\'' => Some('\''),
+            '\n' => Some('\n'),
+            _ => None,
+        };
+        if let Some(c) = simple { self.read_char(); return Ok(Some(c)); }
+        match self.ch {
+            'z' => {
+                self.read_char();
+                while self.ch.is_whitespace() { self.read_char(); }
+                Ok(None)
+            }
+            'x' => {
+                self.read_char();
+                let digits: String = (0..2).map_while(|_| self.ch.is_ascii_hexdigit().then(|| { let c = self.ch; self.read_char(); c })).collect();
+                if digits.len() != 2 { return Err("hexadecimal escape needs exactly two digits".to_string()); }
+                Self::ascii_escape(u32::from_str_radix(&digits, 16).expect("validated hex digits"))
+            }
+            'u' => {
+                self.read_char();
+                if self.ch != '{' { return Err("missing '{' in \\u{XXXX} escape".to_string()); }
+                self.read_char();
+                let mut digits = String::new();
+                while self.ch.is_ascii_hexdigit() { digits.push(self.ch); self.read_char(); }
+                if self.ch != '}' { return Err("missing '}' in \\u{XXXX} escape".to_string()); }
+                self.read_char();
+                u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).map(Some).ok_or_else(|| "invalid unicode escape".to_string())
+            }
+            c if c.is_ascii_digit() => {
+                let digits: String = (0..3).map_while(|_| self.ch.is_ascii_digit().then(|| { let c = self.ch; self.read_char(); c })).collect();
+                Self::ascii_escape(digits.parse().expect("validated decimal digits"))
+            }
+            c => { self.read_char(); Err(format!("invalid escape sequence '\\{}'", c)) }
+        }
+    }
+
+    /// Kita strings are UTF-8, so byte escapes are limited to ASCII; other
+    /// characters are written with `\u{XXXX}`.
+    fn ascii_escape(value: u32) -> Result<Option<char>, String> {
+        if value > 0x7f { return Err(format!("byte escape {} is outside ASCII; use \\u{{{:X}}} instead", value, value)); }
+        Ok(char::from_u32(value))
+    }
+
+    fn read_long_string(&mut self) -> Token {
+        match self.read_long_bracket() {
+            Some(Ok(value)) => Token::Str(value),
+            Some(Err(e)) => Token::Illegal(e),
+            None => Token::Illegal(self.ch.to_string()),
+        }
+    }
+
+    /// Reads a Lua long bracket such as `[[...]]` or `[==[...]==]` starting at the
+    /// current `[`. Returns `None` (consuming nothing) if this is not a long
+    /// bracket opener. As in Lua, a newline right after the opener is skipped.
+    fn read_long_bracket(&mut self) -> Option<Result<String, String>> {
+        let level = self.input[self.read_position..].iter().take_while(|&&c| c == '=').count();
+        if self.input.get(self.read_position + level) != Some(&'[') { return None; }
+        for _ in 0..level + 2 { self.read_char(); }
+        if self.ch == '\r' { self.read_char(); }
+        if self.ch == '\n' { self.read_char(); }
+        let mut value = String::new();
+        loop {
+            match self.ch {
+                '\0' => return Some(Err("unterminated long string".to_string())),
+                ']' if self.input[self.read_position..].iter().take_while(|&&c| c == '=').count() == level
+                    && self.input.get(self.read_position + level) == Some(&']') => {
+                    for _ in 0..level + 2 { self.read_char(); }
+                    return Some(Ok(value));
+                }
+                c => { value.push(c); self.read_char(); }
+            }
+        }
+    }
+
+    /// Reads a decimal integer or float (`12`, `1.5`, `.5`, `3.`, `1e-9`) or a
+    /// hexadecimal integer (`0xFF`). As in Lua, a decimal integer too large for
+    /// `i64` becomes a float. A hexadecimal integer keeps its lowest 64 bits,
+    /// so it may be as large as `u64::MAX`.
+    fn read_number(&mut self) -> Token {
         let pos = self.position;
-        while self.ch.is_digit(10) { self.read_char(); }
-        self.input[pos..self.position].iter().collect::<String>().parse().unwrap_or(0)
+        if self.ch == '0' && matches!(self.peek(), 'x' | 'X') {
+            self.read_char();
+            self.read_char();
+            let digits_start = self.position;
+            while self.ch.is_ascii_hexdigit() { self.read_char(); }
+            let digits: String = self.input[digits_start..self.position].iter().collect();
+            if digits.is_empty() { return Token::Illegal("malformed hexadecimal number".to_string()); }
+            let value = digits.chars().fold(0u64, |acc, c| acc.wrapping_mul(16).wrapping_add(u64::from(c.to_digit(16).expect("hex digit"))));
+            return Token::Int(value);
+        }
+        let mut is_float = false;
+        while self.ch.is_ascii_digit() { self.read_char(); }
+        // A second '.' means this is `1..x` concatenation, not a fraction.
+        if self.ch == '.' && self.peek() != '.' {
+            is_float = true;
+            self.read_char();
+            while self.ch.is_ascii_digit() { self.read_char(); }
+        }
+        if matches!(self.ch, 'e' | 'E') {
+            is_float = true;
+            self.read_char();
+            if matches!(self.ch, '+' | '-') { self.read_char(); }
+            if !self.ch.is_ascii_digit() { return Token::Illegal("malformed number exponent".to_string()); }
+            while self.ch.is_ascii_digit() { self.read_char(); }
+        }
+        let text: String = self.input[pos..self.position].iter().collect();
+        if !is_float {
+            if let Ok(value) = text.parse::<i64>() { return Token::Int(value as u64); }
+        }
+        text.parse().map(Token::Float).unwrap_or_else(|_| Token::Illegal(format!("malformed number '{}'", text)))
     }
 }
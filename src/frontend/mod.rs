@@ -1,5 +1,10 @@
 pub mod ast;
+pub mod diagnostic;
+pub mod infer;
 pub mod lexer;
+pub mod monomorphize;
 pub mod parser;
 pub mod sema;
+pub mod span;
 pub mod token;
+pub mod types;
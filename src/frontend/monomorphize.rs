@@ -0,0 +1,221 @@
+//! Monomorphization: replaces every generic function and record by a copy for
+//! each combination of type arguments the program uses, so that the backend
+//! only ever sees concrete types. A copy is named after the generic and its
+//! type arguments, as in `max__3i64` or `Pair__3i646string`.
+
+use super::{ast::*, diagnostic::Diagnostic, types::Type};
+use std::collections::{HashMap, HashSet};
+
+/// How many copies deep one copy may call for another. A generic that uses
+/// itself with type arguments made from its own, as in `nest({x}, n - 1)`
+/// within `nest<T>(x: T, n: i64)`, would otherwise call for copies forever.
+const MAX_DEPTH: usize = 64;
+
+/// Monomorphizes a program that semantic analysis accepted, and returns the
+/// errors of generics whose copies do not end.
+pub fn monomorphize(program: &mut Program) -> Vec<Diagnostic> {
+    let mut mono = Monomorphizer::default();
+    for stmt in program.iter() {
+        match &stmt.kind {
+            StatementKind::Function { name, type_params, .. } | StatementKind::Record { name, type_params, .. } if !type_params.is_empty() => {
+                mono.generics.insert(name.clone(), stmt.clone());
+            }
+            _ => {}
+        }
+    }
+    let no_args = HashMap::new();
+    for stmt in program.iter_mut() { mono.statement(stmt, &no_args); }
+    // Making a copy may call for more of them.
+    while let Some((generic, args, instance, depth)) = mono.pending.pop() {
+        let mut copy = mono.generics[&generic].clone();
+        if depth > MAX_DEPTH {
+            let message = format!("Generic '{}' uses itself with ever larger type arguments, which would take endless copies of it", generic);
+            return vec![Diagnostic::error(message, copy.span)];
+        }
+        mono.depth = depth;
+        let (StatementKind::Function { name, type_params, .. } | StatementKind::Record { name, type_params, .. }) = &mut copy.kind else {
+            unreachable!("only functions and records are generic")
+        };
+        let args = type_params.drain(..).map(|param| param.name).zip(args).collect();
+        *name = instance;
+        mono.statement(&mut copy, &args);
+        mono.copies.entry(generic).or_default().push(copy);
+    }
+    // The copies take the place of the generic declaration.
+    let mut copies = mono.copies;
+    *program = std::mem::take(program).into_iter().flat_map(|stmt| match &stmt.kind {
+        StatementKind::Function { name, type_params, .. } | StatementKind::Record { name, type_params, .. } if !type_params.is_empty() => {
+            copies.remove(name).unwrap_or_default()
+        }
+        _ => vec![stmt],
+    }).collect();
+    Vec::new()
+}
+
+#[derive(Default)]
+struct Monomorphizer {
+    // The generic declarations, by name.
+    generics: HashMap<String, Statement>,
+    // The names of the copies asked for so far, the generic, type arguments and
+    // depth of those yet to be made, and those made, by generic.
+    instances: HashSet<String>,
+    pending: Vec<(String, Vec<Type>, String, usize)>,
+    copies: HashMap<String, Vec<Statement>>,
+    // How many copies deep the copy being made is; 0 for the program itself.
+    depth: usize,
+}
+
+impl Monomorphizer {
+    /// The name of the copy of `generic` for the type arguments `args`, which
+    /// is made unless it was already asked for.
+    fn instance(&mut self, generic: &str, args: Vec<Type>) -> String {
+        let name = format!("{}__{}", generic, args.iter().map(Self::mangle).collect::<String>());
+        if self.instances.insert(name.clone()) { self.pending.push((generic.to_string(), args, name.clone(), self.depth + 1)); }
+        name
+    }
+
+    /// A C identifier fragment for `ty`, different for every type: names are
+    /// preceded by their length, as in `3i64`, and other types by a letter
+    /// for what they are made of, as in `A3i64` for `{i64}`.
+    fn mangle(ty: &Type) -> String {
+        let each = |types: &[Type]| format!("{}_{}", types.len(), types.iter().map(Self::mangle).collect::<String>());
+        match ty {
+            Type::Array { elem, size: None } => format!("A{}", Self::mangle(elem)),
+            Type::Array { elem, size: Some(size) } => format!("N{}_{}", size, Self::mangle(elem)),
+            Type::Slice(elem) => format!("S{}", Self::mangle(elem)),
+            Type::Optional(inner) => format!("O{}", Self::mangle(inner)),
+            Type::Result { ok, err } => format!("R{}{}", Self::mangle(ok), Self::mangle(err)),
+            Type::Function { params, ret } => format!("F{}{}", each(params), Self::mangle(ret)),
+            Type::Tuple(elems) => format!("T{}", each(elems)),
+            Type::Dyn(interface) => format!("D{}{}", interface.len(), interface),
+            ty => { let name = ty.to_string(); format!("{}{}", name.len(), name) }
+        }
+    }
+
+    /// `ty` with the type parameters in `args` replaced by their types, and
+    /// instances of generic records by their copies.
+    fn concrete(&mut self, ty: &Type, args: &HashMap<String, Type>) -> Type {
+        match ty.substitute(args) {
+            Type::Generic { name, args: type_args } => {
+                let type_args = type_args.iter().map(|arg| self.concrete(arg, args)).collect();
+                Type::Record(self.instance(&name, type_args))
+            }
+            Type::Function { params, ret } => {
+                let params = params.iter().map(|param| self.concrete(param, args)).collect();
+                Type::Function { params, ret: Box::new(self.concrete(&ret, args)) }
+            }
+            Type::Array { elem, size } => Type::Array { elem: Box::new(self.concrete(&elem, args)), size },
+            Type::Slice(elem) => Type::Slice(Box::new(self.concrete(&elem, args))),
+            Type::Optional(inner) => Type::Optional(Box::new(self.concrete(&inner, args))),
+            Type::Result { ok, err } => Type::Result { ok: Box::new(self.concrete(&ok, args)), err: Box::new(self.concrete(&err, args)) },
+            Type::Tuple(elems) => Type::Tuple(elems.iter().map(|elem| self.concrete(elem, args)).collect()),
+            ty => ty,
+        }
+    }
+
+    fn ty(&mut self, ty: &mut Type, args: &HashMap<String, Type>) { *ty = self.concrete(ty, args); }
+
+    fn block(&mut self, block: &mut BlockStatement, args: &HashMap<String, Type>) {
+        for stmt in block.iter_mut() { self.statement(stmt, args); }
+    }
+
+    fn statement(&mut self, stmt: &mut Statement, args: &HashMap<String, Type>) {
+        match &mut stmt.kind {
+            // Generic declarations are only visited in their copies.
+            StatementKind::Function { type_params, .. } | StatementKind::Record { type_params, .. } if !type_params.is_empty() => {}
+            StatementKind::Let { vars, values, .. } => {
+                for var in vars.iter_mut() { self.ty(&mut var.ty, args); }
+                for value in values.iter_mut() { self.expression(value, args); }
+            }
+            StatementKind::Assign { target, value, .. } => { self.expression(target, args); self.expression(value, args); }
+            StatementKind::Return(values) => for value in values.iter_mut() { self.expression(value, args); },
+            StatementKind::Expression(expr) => self.expression(expr, args),
+            StatementKind::Function { params, body, ret, .. } => {
+                for param in params.iter_mut() { self.ty(&mut param.ty, args); }
+                self.ty(ret, args);
+                self.block(body, args);
+            }
+            StatementKind::While { condition, body } | StatementKind::Repeat { body, condition } => {
+                self.expression(condition, args);
+                self.block(body, args);
+            }
+            StatementKind::NumericFor { start, stop, step, body, .. } => {
+                self.expression(start, args);
+                self.expression(stop, args);
+                if let Some(step) = step { self.expression(step, args); }
+                self.block(body, args);
+            }
+            StatementKind::ForIn { iterable, body, .. } => {
+                self.expression(iterable, args);
+                self.block(body, args);
+            }
+            StatementKind::Record { fields, .. } => for field in fields.iter_mut() { self.ty(&mut field.ty, args); },
+            StatementKind::Enum { variants, .. } => {
+                for variant in variants.iter_mut() {
+                    for ty in variant.field_types.iter_mut() { self.ty(ty, args); }
+                }
+            }
+            StatementKind::Interface { .. } | StatementKind::Break | StatementKind::Continue => {}
+        }
+    }
+
+    fn expression(&mut self, expr: &mut Expression, args: &HashMap<String, Type>) {
+        self.ty(&mut expr.ty, args);
+        match &mut expr.kind {
+            ExpressionKind::Instance { name, type_args } => {
+                let type_args = type_args.iter().map(|arg| self.concrete(arg, args)).collect();
+                expr.kind = ExpressionKind::Identifier(self.instance(name, type_args));
+            }
+            // A generic record is constructed by the name of its copy.
+            ExpressionKind::RecordLiteral { name, fields } => {
+                if let Type::Record(instance) = &expr.ty { name.clone_from(instance); }
+                for field in fields.iter_mut() { self.expression(&mut field.value, args); }
+            }
+            ExpressionKind::Prefix { right: inner, .. } | ExpressionKind::Field { target: inner, .. } | ExpressionKind::Try(inner)
+            | ExpressionKind::Wrap(inner) | ExpressionKind::Unwrap(inner) | ExpressionKind::Dyn(inner) => self.expression(inner, args),
+            ExpressionKind::Infix { left, right, .. } | ExpressionKind::Index { target: left, index: right } => {
+                self.expression(left, args);
+                self.expression(right, args);
+            }
+            ExpressionKind::If { condition, consequence, alternative } => {
+                self.expression(condition, args);
+                self.block(consequence, args);
+                if let Some(alt) = alternative { self.block(alt, args); }
+            }
+            ExpressionKind::FunctionLiteral { params, body, captures, .. } => {
+                for param in params.iter_mut() { self.ty(&mut param.ty, args); }
+                for capture in captures.iter_mut() { self.ty(&mut capture.ty, args); }
+                self.block(body, args);
+            }
+            ExpressionKind::Call { function: first, arguments } | ExpressionKind::MethodCall { receiver: first, arguments, .. } => {
+                self.expression(first, args);
+                for arg in arguments.iter_mut() { self.expression(arg, args); }
+            }
+            ExpressionKind::ArrayLiteral(elements) => for element in elements.iter_mut() { self.expression(element, args); },
+            ExpressionKind::Slice { target, start, stop } => {
+                self.expression(target, args);
+                self.expression(start, args);
+                self.expression(stop, args);
+            }
+            ExpressionKind::Match { subject, arms } => {
+                self.expression(subject, args);
+                for arm in arms.iter_mut() {
+                    self.pattern(&mut arm.pattern, args);
+                    if let Some(guard) = &mut arm.guard { self.expression(guard, args); }
+                    self.block(&mut arm.body, args);
+                }
+            }
+            ExpressionKind::Identifier(_) | ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_) | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::Boolean(_) | ExpressionKind::Nil => {}
+        }
+    }
+
+    fn pattern(&mut self, pattern: &mut Pattern, args: &HashMap<String, Type>) {
+        self.ty(&mut pattern.ty, args);
+        match &mut pattern.kind {
+            PatternKind::Literal(literal) => self.expression(literal, args),
+            PatternKind::Variant { fields, .. } => for field in fields.iter_mut() { self.pattern(field, args); },
+            PatternKind::Wildcard | PatternKind::Binding(_) => {}
+        }
+    }
+}
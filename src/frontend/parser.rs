@@ -1,119 +1,697 @@
-use super::{ast::*, lexer::Lexer, token::Token};
+use super::{ast::*, diagnostic::Diagnostic, lexer::Lexer, span::Span, token::Token, types::Type};
 use std::mem;
 
-// 'Prefix' variant removed
+/// Binding strength of operators, weakest first, following Lua 5.4.
 #[derive(PartialEq, PartialOrd)]
-enum Precedence { Lowest, Equals, LessGreater, Sum, Product, Call }
+enum Precedence { Lowest, Or, And, Comparison, BitOr, BitXor, BitAnd, Shift, Concat, Sum, Product, Prefix, Power, Call }
 
 pub struct Parser {
-    lexer: Lexer, current_token: Token, peek_token: Token, pub errors: Vec<String>,
+    lexer: Lexer, current_token: Token, peek_token: Token, current_span: Span, peek_span: Span,
+    /// Blocks (`if`, loops, functions) entered but not yet closed by their
+    /// `end`, used to skip whole constructs after a syntax error.
+    open_blocks: usize,
+    /// Whether a `|` ends the current match arm rather than being a bitwise or.
+    in_match_arm: bool,
+    pub errors: Vec<Diagnostic>,
 }
 
-// ... the rest of the file is identical to the previous version ...
-// (You only need to change the Precedence enum at the top)
 impl Parser {
     pub fn new(lexer: Lexer) -> Self {
-        let mut p = Self { lexer, current_token: Token::Eof, peek_token: Token::Eof, errors: vec![] };
+        let mut p = Self {
+            lexer, current_token: Token::Eof, peek_token: Token::Eof,
+            current_span: Span::default(), peek_span: Span::default(), open_blocks: 0, in_match_arm: false, errors: vec![],
+        };
         p.next_token(); p.next_token();
         p
     }
 
     fn next_token(&mut self) {
-        self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token();
+        let next = self.lexer.next_token();
+        self.current_token = mem::replace(&mut self.peek_token, next.token);
+        self.current_span = mem::replace(&mut self.peek_span, next.span);
     }
 
     pub fn parse_program(&mut self) -> Program {
         let mut program = Vec::new();
         while self.current_token != Token::Eof {
-            if let Some(stmt) = self.parse_statement() { program.push(stmt); }
-            self.next_token();
+            if let Some(stmt) = self.parse_next_statement() { program.push(stmt); }
         }
         program
     }
 
-    fn parse_statement(&mut self) -> Option<Statement> {
+    /// Parses the statement at the current token and moves on to the one after
+    /// it. After a syntax error, skips ahead to where the next statement likely
+    /// starts, so that one mistake is reported once instead of cascading.
+    fn parse_next_statement(&mut self) -> Option<Statement> {
+        let (start, open_blocks) = (self.current_span.clone(), self.open_blocks);
+        let stmt = self.parse_statement();
+        if stmt.is_some() { self.next_token(); } else {
+            let unclosed = self.open_blocks - open_blocks;
+            self.open_blocks = open_blocks;
+            self.synchronize(&start, unclosed);
+        }
+        stmt
+    }
+
+    /// Skips the rest of a statement that failed to parse, stopping at a
+    /// keyword that starts a statement, a block terminator, or the first token
+    /// on a new line. The `unclosed` blocks the statement opened are skipped
+    /// together with their `end`, so that it is not taken to close an
+    /// enclosing block. The current token was already seen by the failed parse.
+    fn synchronize(&mut self, start: &Span, mut unclosed: usize) {
+        if unclosed == 0 && self.current_span.byte_range != start.byte_range && self.at_statement_boundary() { return; }
+        loop {
+            let line = self.current_span.line;
+            self.next_token();
+            match self.current_token {
+                Token::Eof => return,
+                Token::End | Token::Until if unclosed > 0 => unclosed -= 1,
+                Token::If | Token::While | Token::For | Token::Repeat | Token::Record | Token::Enum | Token::Interface | Token::Match | Token::Function if unclosed > 0 => unclosed += 1,
+                Token::Function if !matches!(self.peek_token, Token::Ident(_)) => unclosed += 1,
+                _ if unclosed == 0 && (self.at_statement_boundary() || self.current_span.line > line) => return,
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether the current token starts a statement or ends a block.
+    fn at_statement_boundary(&self) -> bool {
         match self.current_token {
-            Token::Let => self.parse_let_statement(),
+            Token::Let | Token::Const | Token::Return | Token::If | Token::While | Token::For | Token::Repeat | Token::Record
+            | Token::Enum | Token::Interface | Token::Match | Token::Break | Token::Continue | Token::End | Token::Else | Token::Until | Token::Eof => true,
+            Token::Function => matches!(self.peek_token, Token::Ident(_)),
+            _ => false,
+        }
+    }
+
+    fn parse_statement(&mut self) -> Option<Statement> {
+        let start = self.current_span.clone();
+        let kind = match self.current_token {
+            Token::Let | Token::Const => self.parse_let_statement(),
             Token::Return => self.parse_return_statement(),
+            Token::Function if matches!(self.peek_token, Token::Ident(_)) => self.parse_function_statement(),
+            Token::While => self.parse_while_statement(),
+            Token::For => self.parse_for_statement(),
+            Token::Repeat => self.parse_repeat_statement(),
+            Token::Record => self.parse_record_statement(),
+            Token::Enum => self.parse_enum_statement(),
+            Token::Interface => self.parse_interface_statement(),
+            Token::Break => Some(StatementKind::Break),
+            Token::Continue => Some(StatementKind::Continue),
             _ => self.parse_expression_statement(),
+        }?;
+        Some(Statement { kind, span: self.span_from(&start) })
+    }
+
+    fn parse_let_statement(&mut self) -> Option<StatementKind> {
+        let is_const = self.current_token == Token::Const;
+        let mut vars = Vec::new();
+        loop {
+            if !self.expect_peek_is_ident() { return None; }
+            let start = self.current_span.clone();
+            let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+            let annotation = self.parse_optional_annotation()?;
+            vars.push(Variable { name, annotation, span: self.span_from(&start), ty: Type::Unknown, shared: false });
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        if !self.expect_peek(Token::Assign) { return None; }
+        let mut values = Vec::new();
+        loop {
+            self.next_token();
+            values.push(self.parse_expression(Precedence::Lowest)?);
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        Some(StatementKind::Let { vars, values, is_const })
+    }
+
+    /// Parses `function name(...)`, a method `function Type:name(...)`, or a
+    /// generic function `function name<T, ...>(...)`.
+    fn parse_function_statement(&mut self) -> Option<StatementKind> {
+        self.next_token();
+        let (mut name, type_span) = if let Token::Ident(n) = self.current_token.clone() { (n, self.current_span.clone()) } else { return None; };
+        let mut receiver = None;
+        if self.peek_token == Token::Colon {
+            self.next_token();
+            // Skip the whole function, as `parse_function_rest` would.
+            if !self.expect_peek_is_ident() { self.open_blocks += 1; return None; }
+            let method = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+            receiver = Some(mem::replace(&mut name, method));
+        }
+        let Some(type_params) = self.parse_type_params() else { self.open_blocks += 1; return None; };
+        let (mut params, return_annotation, body) = self.parse_function_rest()?;
+        if let Some(receiver) = &receiver {
+            let annotation = TypeExpr { kind: TypeExprKind::Named(receiver.clone()), span: type_span.clone() };
+            params.insert(0, Param { name: "self".to_string(), annotation: Some(annotation), span: type_span, ty: Type::Unknown, shared: false });
         }
+        Some(StatementKind::Function { name, type_params, receiver, params, return_annotation, body, ret: Type::Unknown })
+    }
+
+    fn parse_while_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        if !self.expect_peek(Token::Do) { return None; }
+        let body = self.parse_block_statement();
+        if !self.expect_current_end("while") { return None; }
+        Some(StatementKind::While { condition, body })
     }
-    
-    fn parse_let_statement(&mut self) -> Option<Statement> {
+
+    fn parse_for_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
         if !self.expect_peek_is_ident() { return None; }
-        let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        let var = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        if matches!(self.peek_token, Token::Comma | Token::In) { return self.parse_for_in_rest(var); }
         if !self.expect_peek(Token::Assign) { return None; }
         self.next_token();
-        let value = self.parse_expression(Precedence::Lowest)?;
-        Some(Statement::Let { name, value })
+        let start = self.parse_expression(Precedence::Lowest)?;
+        if !self.expect_peek(Token::Comma) { return None; }
+        self.next_token();
+        let stop = self.parse_expression(Precedence::Lowest)?;
+        let step = if self.peek_token == Token::Comma {
+            self.next_token();
+            self.next_token();
+            Some(Box::new(self.parse_expression(Precedence::Lowest)?))
+        } else { None };
+        if !self.expect_peek(Token::Do) { return None; }
+        let body = self.parse_block_statement();
+        if !self.expect_current_end("for") { return None; }
+        Some(StatementKind::NumericFor { var, start: Box::new(start), stop: Box::new(stop), step, body })
     }
-    
-    fn parse_return_statement(&mut self) -> Option<Statement> {
+
+    /// Parses the rest of `for index[, value] in iterable do ... end`.
+    fn parse_for_in_rest(&mut self, index: String) -> Option<StatementKind> {
+        let value = if self.peek_token == Token::Comma {
+            self.next_token();
+            if !self.expect_peek_is_ident() { return None; }
+            if let Token::Ident(n) = self.current_token.clone() { Some(n) } else { return None; }
+        } else { None };
+        if !self.expect_peek(Token::In) { return None; }
+        self.next_token();
+        let iterable = self.parse_expression(Precedence::Lowest)?;
+        if !self.expect_peek(Token::Do) { return None; }
+        let body = self.parse_block_statement();
+        if !self.expect_current_end("for") { return None; }
+        Some(StatementKind::ForIn { index, value, iterable, body })
+    }
+
+    fn parse_repeat_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
+        let body = self.parse_block_statement();
+        if self.current_token != Token::Until { self.error_at_current(format!("Expected 'until' to close 'repeat' block, got {:?}", self.current_token)); return None; }
+        self.open_blocks -= 1;
+        self.next_token();
+        let condition = self.parse_expression(Precedence::Lowest)?;
+        Some(StatementKind::Repeat { body, condition })
+    }
+
+    /// Parses `record Name field: type, ... end`, or `record Name: Shape, ...`
+    /// with the interfaces it implements. Commas between fields are optional.
+    fn parse_record_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
+        if !self.expect_peek_is_ident() { return None; }
+        let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        let type_params = self.parse_type_params()?;
+        let interfaces = self.parse_interface_list()?;
+        let mut fields = Vec::new();
+        self.next_token();
+        while !matches!(self.current_token, Token::End | Token::Eof) {
+            let Token::Ident(field) = self.current_token.clone() else {
+                self.error_at_current(format!("Expected a field name, got {:?}", self.current_token));
+                return None;
+            };
+            let start = self.current_span.clone();
+            if !self.expect_peek(Token::Colon) { return None; }
+            let annotation = self.parse_type()?;
+            fields.push(Field { name: field, annotation, span: self.span_from(&start), ty: Type::Unknown });
+            if self.peek_token == Token::Comma { self.next_token(); }
+            self.next_token();
+        }
+        if !self.expect_current_end("record") { return None; }
+        Some(StatementKind::Record { name, type_params, interfaces, fields })
+    }
+
+    /// Parses the interfaces `: Shape, ...` a record or enum implements if the
+    /// next token is a colon.
+    fn parse_interface_list(&mut self) -> Option<Vec<TypeExpr>> {
+        let mut interfaces = Vec::new();
+        if self.peek_token != Token::Colon { return Some(interfaces); }
+        self.next_token();
+        loop {
+            if !self.expect_peek_is_ident() { return None; }
+            let Token::Ident(name) = self.current_token.clone() else { return None; };
+            interfaces.push(TypeExpr { kind: TypeExprKind::Named(name), span: self.current_span.clone() });
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        Some(interfaces)
+    }
+
+    /// Parses `interface Name function method(self, param: type, ...): type ... end`.
+    /// The methods have no body, and every parameter but `self` is annotated.
+    fn parse_interface_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
+        if !self.expect_peek_is_ident() { return None; }
+        let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        let mut methods = Vec::new();
+        self.next_token();
+        while !matches!(self.current_token, Token::End | Token::Eof) {
+            if self.current_token != Token::Function {
+                self.error_at_current(format!("Expected a method declaration, got {:?}", self.current_token));
+                return None;
+            }
+            let start = self.current_span.clone();
+            if !self.expect_peek_is_ident() { return None; }
+            let Token::Ident(method) = self.current_token.clone() else { return None; };
+            if !self.expect_peek(Token::LParen) { return None; }
+            let mut params = self.parse_function_parameters()?;
+            let return_annotation = self.parse_optional_annotation()?;
+            let span = self.span_from(&start);
+            self.next_token();
+            // The method is left out, but the rest of the interface is still parsed.
+            if !params.first().is_some_and(|param| param.name == "self" && param.annotation.is_none()) {
+                self.errors.push(Diagnostic::error(format!("Interface method '{}' must take 'self' as its first parameter, as in {}(self)", method, method), span));
+                continue;
+            }
+            params.remove(0);
+            methods.push(InterfaceMethod { name: method, params, return_annotation, span, ty: Type::Unknown });
+        }
+        if !self.expect_current_end("interface") { return None; }
+        Some(StatementKind::Interface { name, methods })
+    }
+
+    /// Parses the type parameters `<T, U: Bound, ...>` of a generic declaration
+    /// if the next token opens them.
+    fn parse_type_params(&mut self) -> Option<Vec<TypeParam>> {
+        let mut type_params = Vec::new();
+        if self.peek_token != Token::Lt { return Some(type_params); }
+        self.next_token();
+        loop {
+            if !self.expect_peek_is_ident() { return None; }
+            let (Token::Ident(name), start) = (self.current_token.clone(), self.current_span.clone()) else { return None; };
+            let mut bound = None;
+            if self.peek_token == Token::Colon {
+                self.next_token();
+                if !self.expect_peek_is_ident() { return None; }
+                if let Token::Ident(b) = self.current_token.clone() { bound = Some(b); }
+            }
+            type_params.push(TypeParam { name, bound, span: self.span_from(&start) });
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        if !self.expect_peek(Token::Gt) { return None; }
+        Some(type_params)
+    }
+
+    /// Parses `enum Name Variant(type, ...) | Variant ... end`, or `enum Name:
+    /// Shape, ...` with the interfaces it implements. A `|` before the first
+    /// variant is allowed.
+    fn parse_enum_statement(&mut self) -> Option<StatementKind> {
+        self.open_blocks += 1;
+        if !self.expect_peek_is_ident() { return None; }
+        let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        let interfaces = self.parse_interface_list()?;
+        if self.peek_token == Token::Pipe { self.next_token(); }
+        let mut variants = Vec::new();
+        loop {
+            if !self.expect_peek_is_ident() { return None; }
+            let (variant, start) = if let Token::Ident(n) = self.current_token.clone() { (n, self.current_span.clone()) } else { return None; };
+            let mut fields = Vec::new();
+            if self.peek_token == Token::LParen {
+                self.next_token();
+                loop {
+                    fields.push(self.parse_type()?);
+                    if self.peek_token != Token::Comma { break; }
+                    self.next_token();
+                }
+                if !self.expect_peek(Token::RParen) { return None; }
+            }
+            variants.push(Variant { name: variant, fields, span: self.span_from(&start), field_types: Vec::new() });
+            if self.peek_token != Token::Pipe { break; }
+            self.next_token();
+        }
         self.next_token();
-        let return_value = self.parse_expression(Precedence::Lowest)?;
-        Some(Statement::Return(return_value))
+        if !self.expect_current_end("enum") { return None; }
+        Some(StatementKind::Enum { name, interfaces, variants })
     }
 
-    fn parse_expression_statement(&mut self) -> Option<Statement> {
+    /// A `return` with no values is one followed by the end of its block or
+    /// by a new line.
+    fn parse_return_statement(&mut self) -> Option<StatementKind> {
+        let mut values = Vec::new();
+        let block_ends = matches!(self.peek_token, Token::End | Token::Else | Token::Until | Token::Eof) || (self.in_match_arm && self.peek_token == Token::Pipe);
+        if block_ends || self.peek_span.line > self.current_span.line { return Some(StatementKind::Return(values)); }
+        loop {
+            self.next_token();
+            values.push(self.parse_expression(Precedence::Lowest)?);
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        Some(StatementKind::Return(values))
+    }
+
+    fn parse_expression_statement(&mut self) -> Option<StatementKind> {
         let expression = self.parse_expression(Precedence::Lowest)?;
-        Some(Statement::Expression(expression))
+        let op = match self.peek_token {
+            Token::Assign => None,
+            Token::PlusAssign => Some(Token::Plus),
+            Token::MinusAssign => Some(Token::Minus),
+            Token::AsteriskAssign => Some(Token::Asterisk),
+            Token::SlashAssign => Some(Token::Slash),
+            _ => return Some(StatementKind::Expression(expression)),
+        };
+        self.next_token();
+        self.next_token();
+        let value = self.parse_expression(Precedence::Lowest)?;
+        Some(StatementKind::Assign { target: Box::new(expression), op, value })
     }
 
     fn parse_block_statement(&mut self) -> BlockStatement {
         let mut statements = Vec::new();
         self.next_token();
-        while !matches!(self.current_token, Token::End | Token::Else | Token::Eof) {
-            if let Some(stmt) = self.parse_statement() { statements.push(stmt); }
-            self.next_token();
+        loop {
+            // Inside a match arm, a `|` starts the next arm.
+            let arm_ends = self.in_match_arm && self.current_token == Token::Pipe;
+            if arm_ends || matches!(self.current_token, Token::End | Token::Else | Token::Until | Token::Eof) { break; }
+            if let Some(stmt) = self.parse_next_statement() { statements.push(stmt); }
         }
         statements
     }
-    
+
     fn parse_expression(&mut self, precedence: Precedence) -> Option<Expression> {
-        let mut left_exp = match self.current_token.clone() {
-            Token::Ident(name) => Expression::Identifier(name),
-            Token::Int(val) => Expression::IntegerLiteral(val),
-            Token::True => Expression::Boolean(true),
-            Token::False => Expression::Boolean(false),
-            Token::LParen => { self.next_token(); let exp = self.parse_expression(Precedence::Lowest)?; if !self.expect_peek(Token::RParen) { return None; } exp },
+        let start = self.current_span.clone();
+        let kind = match self.current_token.clone() {
+            Token::Ident(name) => ExpressionKind::Identifier(name),
+            Token::Int(val) => ExpressionKind::IntegerLiteral(i128::from(val)),
+            Token::Float(val) => ExpressionKind::FloatLiteral(val),
+            Token::Str(val) => ExpressionKind::StringLiteral(val),
+            Token::True => ExpressionKind::Boolean(true),
+            Token::False => ExpressionKind::Boolean(false),
+            Token::Nil => ExpressionKind::Nil,
+            Token::LParen => { self.next_token(); let exp = self.nested(|p| p.parse_expression(Precedence::Lowest))?; if !self.expect_peek(Token::RParen) { return None; } exp.kind },
             Token::If => self.parse_if_expression()?,
-            _ => { self.errors.push(format!("No prefix parse function for {:?}", self.current_token)); return None; }
+            Token::Match => self.parse_match_expression()?,
+            Token::LBrace => self.parse_array_literal()?,
+            Token::Function => { let (params, return_annotation, body) = self.parse_function_rest()?; ExpressionKind::FunctionLiteral { params, return_annotation, body, captures: Vec::new(), escapes: true } },
+            Token::Hash | Token::Minus | Token::Not | Token::Tilde => self.parse_prefix_expression()?,
+            Token::Illegal(what) => { self.error_at_current(format!("Invalid token: {}", what)); return None; }
+            _ => { self.error_at_current(format!("No prefix parse function for {:?}", self.current_token)); return None; }
         };
-        
+        let mut left_exp = Expression::new(kind, self.span_from(&start));
+
         while precedence < self.peek_precedence() {
-            match self.peek_token {
-                Token::LParen => { self.next_token(); left_exp = self.parse_call_expression(left_exp)?; },
-                _ => { self.next_token(); left_exp = self.parse_infix_expression(left_exp)?; }
-            }
+            let kind = match self.peek_token {
+                Token::LParen => { self.next_token(); self.parse_call_expression(left_exp)? },
+                Token::LBracket => { self.next_token(); self.parse_index_expression(left_exp)? },
+                Token::Colon => {
+                    self.next_token();
+                    if !self.expect_peek_is_ident() { return None; }
+                    let method = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+                    if !self.expect_peek(Token::LParen) { return None; }
+                    let arguments = self.parse_call_arguments()?;
+                    ExpressionKind::MethodCall { receiver: Box::new(left_exp), method, arguments }
+                }
+                Token::Question => { self.next_token(); ExpressionKind::Try(Box::new(left_exp)) }
+                Token::Dot => {
+                    self.next_token();
+                    if !self.expect_peek_is_ident() { return None; }
+                    let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+                    ExpressionKind::Field { target: Box::new(left_exp), name }
+                }
+                // Only a record name can be followed by a constructor's `{`.
+                Token::LBrace => {
+                    let ExpressionKind::Identifier(name) = &left_exp.kind else { break };
+                    let name = name.clone();
+                    self.next_token();
+                    self.parse_record_literal(name)?
+                }
+                _ => { self.next_token(); self.parse_infix_expression(left_exp)? }
+            };
+            left_exp = Expression::new(kind, self.span_from(&start));
         }
         Some(left_exp)
     }
 
-    fn parse_infix_expression(&mut self, left: Expression) -> Option<Expression> {
+    /// Parses `match subject with | pattern [if guard] -> body ... end`. An arm's
+    /// body runs up to the next arm's `|`, so a bitwise or in a body has to be
+    /// put in brackets.
+    fn parse_match_expression(&mut self) -> Option<ExpressionKind> {
+        self.open_blocks += 1;
+        self.next_token();
+        let subject = self.nested(|p| p.parse_expression(Precedence::Lowest))?;
+        if !self.expect_peek(Token::With) { return None; }
+        self.next_token();
+        let mut arms = Vec::new();
+        while self.current_token == Token::Pipe {
+            let start = self.current_span.clone();
+            self.next_token();
+            let pattern = self.parse_pattern()?;
+            let guard = if self.peek_token == Token::If {
+                self.next_token();
+                self.next_token();
+                Some(self.nested(|p| p.parse_expression(Precedence::Lowest))?)
+            } else { None };
+            if !self.expect_peek(Token::Arrow) { return None; }
+            let outer = mem::replace(&mut self.in_match_arm, true);
+            let body = self.parse_block_statement();
+            self.in_match_arm = outer;
+            arms.push(MatchArm { pattern, guard, body, span: self.span_from(&start) });
+        }
+        if !self.expect_current_end("match") { return None; }
+        Some(ExpressionKind::Match { subject: Box::new(subject), arms })
+    }
+
+    /// Parses the pattern starting at the current token.
+    fn parse_pattern(&mut self) -> Option<Pattern> {
+        let start = self.current_span.clone();
+        let kind = match self.current_token.clone() {
+            Token::Ident(name) if name == "_" => PatternKind::Wildcard,
+            Token::Ident(name) if self.peek_token == Token::LParen => {
+                self.next_token();
+                let mut fields = Vec::new();
+                while self.peek_token != Token::RParen {
+                    self.next_token();
+                    fields.push(self.parse_pattern()?);
+                    if self.peek_token != Token::Comma { break; }
+                    self.next_token();
+                }
+                if !self.expect_peek(Token::RParen) { return None; }
+                PatternKind::Variant { name, fields }
+            }
+            Token::Ident(name) => PatternKind::Binding(name),
+            Token::Int(_) | Token::Float(_) | Token::Str(_) | Token::True | Token::False => PatternKind::Literal(Box::new(self.parse_expression(Precedence::Call)?)),
+            Token::Minus if matches!(self.peek_token, Token::Int(_) | Token::Float(_)) => PatternKind::Literal(Box::new(self.parse_prefix_expression().map(|kind| Expression::new(kind, self.span_from(&start)))?)),
+            _ => { self.error_at_current(format!("Expected a pattern, got {:?}", self.current_token)); return None; }
+        };
+        Some(Pattern { kind, span: self.span_from(&start), ty: Type::Unknown })
+    }
+
+    /// Runs `parse` on a bracketed part of the source, where `|` is a bitwise
+    /// or even inside a match arm.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = mem::replace(&mut self.in_match_arm, false);
+        let result = parse(self);
+        self.in_match_arm = outer;
+        result
+    }
+
+    fn parse_prefix_expression(&mut self) -> Option<ExpressionKind> {
+        let op = self.current_token.clone();
+        self.next_token();
+        let right = self.parse_expression(Precedence::Prefix)?;
+        // Negative number literals are literals too, so that e.g. `-128` can be an `i8`.
+        match (&op, &right.kind) {
+            (Token::Minus, ExpressionKind::IntegerLiteral(value)) => Some(ExpressionKind::IntegerLiteral(value.wrapping_neg())),
+            (Token::Minus, ExpressionKind::FloatLiteral(value)) => Some(ExpressionKind::FloatLiteral(-value)),
+            _ => Some(ExpressionKind::Prefix { op, right: Box::new(right) }),
+        }
+    }
+
+    fn parse_infix_expression(&mut self, left: Expression) -> Option<ExpressionKind> {
         let op = self.current_token.clone();
-        let precedence = self.cur_precedence();
+        // `..` and `^` are right associative: their right operand may itself
+        // contain the same operator. `-x ^ 2` still means `-(x ^ 2)`.
+        let precedence = match op { Token::Concat => Precedence::Shift, Token::Caret => Precedence::Prefix, _ => self.cur_precedence() };
         self.next_token();
         let right = self.parse_expression(precedence)?;
-        Some(Expression::Infix { op, left: Box::new(left), right: Box::new(right) })
+        Some(ExpressionKind::Infix { op, left: Box::new(left), right: Box::new(right) })
+    }
+
+    /// Parses `{a, b, c}`; as in Lua, a trailing comma is allowed.
+    fn parse_array_literal(&mut self) -> Option<ExpressionKind> {
+        let mut elements = Vec::new();
+        while self.peek_token != Token::RBrace {
+            self.next_token();
+            elements.push(self.nested(|p| p.parse_expression(Precedence::Lowest))?);
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        if !self.expect_peek(Token::RBrace) { return None; }
+        Some(ExpressionKind::ArrayLiteral(elements))
+    }
+
+    /// Parses the `{field = value, ...}` of a record constructor; as in Lua, a
+    /// trailing comma is allowed.
+    fn parse_record_literal(&mut self, name: String) -> Option<ExpressionKind> {
+        let mut fields = Vec::new();
+        while self.peek_token != Token::RBrace {
+            if !self.expect_peek_is_ident() { return None; }
+            let (field, span) = if let Token::Ident(n) = self.current_token.clone() { (n, self.current_span.clone()) } else { return None; };
+            if !self.expect_peek(Token::Assign) { return None; }
+            self.next_token();
+            let value = self.nested(|p| p.parse_expression(Precedence::Lowest))?;
+            fields.push(FieldInit { name: field, span, value });
+            if self.peek_token != Token::Comma { break; }
+            self.next_token();
+        }
+        if !self.expect_peek(Token::RBrace) { return None; }
+        Some(ExpressionKind::RecordLiteral { name, fields })
     }
 
-    fn parse_if_expression(&mut self) -> Option<Expression> {
+    /// Parses `[index]` or `[start..stop]` following `target`.
+    fn parse_index_expression(&mut self, target: Expression) -> Option<ExpressionKind> {
+        self.next_token();
+        let index = self.nested(|p| p.parse_expression(Precedence::Lowest))?;
+        if !self.expect_peek(Token::RBracket) { return None; }
+        // Indices are never strings, so a `..` at the top of the index is a slice.
+        Some(match index.kind {
+            ExpressionKind::Infix { op: Token::Concat, left, right } => ExpressionKind::Slice { target: Box::new(target), start: left, stop: right },
+            _ => ExpressionKind::Index { target: Box::new(target), index: Box::new(index) },
+        })
+    }
+
+    fn parse_if_expression(&mut self) -> Option<ExpressionKind> {
+        self.open_blocks += 1;
         self.next_token();
         let condition = self.parse_expression(Precedence::Lowest)?;
         if !self.expect_peek(Token::Then) { return None; }
         let consequence = self.parse_block_statement();
         let alternative = if self.current_token == Token::Else { Some(self.parse_block_statement()) } else { None };
-        if !matches!(self.current_token, Token::End) { self.errors.push(format!("Expected 'end' to close 'if' block, got {:?}", self.current_token)); return None; }
-        Some(Expression::If { condition: Box::new(condition), consequence, alternative })
+        if !self.expect_current_end("if") { return None; }
+        Some(ExpressionKind::If { condition: Box::new(condition), consequence, alternative })
+    }
+
+    /// Parses `(params) [: type] body end`, shared by named declarations and
+    /// function literals.
+    fn parse_function_rest(&mut self) -> Option<(Vec<Param>, Option<TypeExpr>, BlockStatement)> {
+        self.open_blocks += 1;
+        if !self.expect_peek(Token::LParen) { return None; }
+        let params = self.parse_function_parameters()?;
+        let return_annotation = self.parse_optional_annotation()?;
+        let body = self.nested(|p| p.parse_block_statement());
+        if !self.expect_current_end("function") { return None; }
+        Some((params, return_annotation, body))
+    }
+
+    fn parse_function_parameters(&mut self) -> Option<Vec<Param>> {
+        let mut params = Vec::new();
+        if self.peek_token == Token::RParen { self.next_token(); return Some(params); }
+        params.push(self.parse_parameter()?);
+        while self.peek_token == Token::Comma {
+            self.next_token();
+            params.push(self.parse_parameter()?);
+        }
+        if !self.expect_peek(Token::RParen) { return None; }
+        Some(params)
+    }
+
+    fn parse_parameter(&mut self) -> Option<Param> {
+        if !self.expect_peek_is_ident() { return None; }
+        let start = self.current_span.clone();
+        let name = if let Token::Ident(n) = self.current_token.clone() { n } else { return None; };
+        let annotation = self.parse_optional_annotation()?;
+        Some(Param { name, annotation, span: self.span_from(&start), ty: Type::Unknown, shared: false })
     }
-    
-    fn parse_call_expression(&mut self, function: Expression) -> Option<Expression> {
+
+    /// Parses a `: type` annotation if the next token is a colon. The outer
+    /// `None` signals a syntax error.
+    fn parse_optional_annotation(&mut self) -> Option<Option<TypeExpr>> {
+        if self.peek_token != Token::Colon { return Some(None); }
+        self.next_token();
+        self.parse_type().map(Some)
+    }
+
+    /// Parses a type starting at the next token: a name, `Name<T, ...>`, `{T}`,
+    /// `{T; N}`, `[T]`, `dyn Interface`, `function(T, ...): R` or the return type `(T, U, ...)`,
+    /// any of which may be followed by a `?` making it optional.
+    fn parse_type(&mut self) -> Option<TypeExpr> {
+        self.next_token();
+        let start = self.current_span.clone();
+        let mut kind = match self.current_token.clone() {
+            Token::Ident(name) if self.peek_token == Token::Lt => {
+                self.next_token();
+                let mut args = vec![self.parse_type()?];
+                while self.peek_token == Token::Comma {
+                    self.next_token();
+                    args.push(self.parse_type()?);
+                }
+                // The `>>` closing two lists at once is read as a shift.
+                if self.peek_token == Token::ShiftRight { self.peek_token = Token::Gt; } else if !self.expect_peek(Token::Gt) { return None; }
+                TypeExprKind::Generic { name, args }
+            }
+            Token::Ident(name) => TypeExprKind::Named(name),
+            Token::Dyn => {
+                if !self.expect_peek_is_ident() { return None; }
+                let Token::Ident(interface) = self.current_token.clone() else { return None; };
+                TypeExprKind::Dyn(interface)
+            }
+            Token::Function => {
+                if !self.expect_peek(Token::LParen) { return None; }
+                let mut params = Vec::new();
+                while self.peek_token != Token::RParen {
+                    params.push(self.parse_type()?);
+                    if self.peek_token != Token::Comma { break; }
+                    self.next_token();
+                }
+                if !self.expect_peek(Token::RParen) { return None; }
+                let ret = self.parse_optional_annotation()?.map(Box::new);
+                TypeExprKind::Function { params, ret }
+            }
+            Token::LBrace => {
+                let elem = Box::new(self.parse_type()?);
+                let size = if self.peek_token == Token::Semicolon {
+                    self.next_token();
+                    if !self.expect_peek(Token::Int(0)) { return None; }
+                    if let Token::Int(size) = self.current_token { Some(size) } else { return None; }
+                } else { None };
+                if !self.expect_peek(Token::RBrace) { return None; }
+                TypeExprKind::Array { elem, size }
+            }
+            Token::LBracket => {
+                let elem = Box::new(self.parse_type()?);
+                if !self.expect_peek(Token::RBracket) { return None; }
+                TypeExprKind::Slice(elem)
+            }
+            Token::LParen => {
+                let mut elems = vec![self.parse_type()?];
+                while self.peek_token == Token::Comma {
+                    self.next_token();
+                    elems.push(self.parse_type()?);
+                }
+                if !self.expect_peek(Token::RParen) { return None; }
+                TypeExprKind::Tuple(elems)
+            }
+            _ => { self.error_at_current(format!("Expected a type, got {:?}", self.current_token)); return None; }
+        };
+        while self.peek_token == Token::Question {
+            let inner = TypeExpr { kind, span: self.span_from(&start) };
+            self.next_token();
+            kind = TypeExprKind::Optional(Box::new(inner));
+        }
+        Some(TypeExpr { kind, span: self.span_from(&start) })
+    }
+
+    fn parse_call_expression(&mut self, function: Expression) -> Option<ExpressionKind> {
         let arguments = self.parse_call_arguments()?;
-        Some(Expression::Call { function: Box::new(function), arguments })
+        Some(ExpressionKind::Call { function: Box::new(function), arguments })
     }
 
     fn parse_call_arguments(&mut self) -> Option<Vec<Expression>> {
+        self.nested(|p| p.parse_call_arguments_list())
+    }
+
+    fn parse_call_arguments_list(&mut self) -> Option<Vec<Expression>> {
         let mut args = Vec::new();
         if self.peek_token == Token::RParen { self.next_token(); return Some(args); }
         self.next_token();
@@ -129,23 +707,46 @@ impl Parser {
 
     fn token_to_precedence(tok: &Token) -> Precedence {
         match tok {
-            Token::Eq | Token::NotEq => Precedence::Equals,
-            Token::Lt | Token::Gt => Precedence::LessGreater,
+            Token::Or => Precedence::Or,
+            Token::And => Precedence::And,
+            Token::Eq | Token::NotEq | Token::Lt | Token::Gt | Token::LtEq | Token::GtEq => Precedence::Comparison,
+            Token::Pipe => Precedence::BitOr,
+            Token::Tilde => Precedence::BitXor,
+            Token::Ampersand => Precedence::BitAnd,
+            Token::ShiftLeft | Token::ShiftRight => Precedence::Shift,
+            Token::Concat => Precedence::Concat,
             Token::Plus | Token::Minus => Precedence::Sum,
-            Token::Slash | Token::Asterisk => Precedence::Product,
-            Token::LParen => Precedence::Call,
+            Token::Slash | Token::Asterisk | Token::FloorDiv | Token::Percent => Precedence::Product,
+            Token::Caret => Precedence::Power,
+            Token::LParen | Token::LBracket | Token::LBrace | Token::Dot | Token::Colon | Token::Question => Precedence::Call,
             _ => Precedence::Lowest,
         }
     }
-    fn peek_precedence(&self) -> Precedence { Self::token_to_precedence(&self.peek_token) }
+    fn peek_precedence(&self) -> Precedence {
+        if self.in_match_arm && self.peek_token == Token::Pipe { return Precedence::Lowest; }
+        Self::token_to_precedence(&self.peek_token)
+    }
     fn cur_precedence(&self) -> Precedence { Self::token_to_precedence(&self.current_token) }
-    
+
+    /// The span from `start` up to and including the current token.
+    fn span_from(&self, start: &Span) -> Span { start.join(&self.current_span) }
+
+    fn error_at_current(&mut self, message: String) { self.errors.push(Diagnostic::error(message, self.current_span.clone())); }
+    fn error_at_peek(&mut self, message: String) { self.errors.push(Diagnostic::error(message, self.peek_span.clone())); }
+
+    /// Checks that a block opened by `construct` was closed with `end`.
+    fn expect_current_end(&mut self, construct: &str) -> bool {
+        if self.current_token == Token::End { self.open_blocks -= 1; return true; }
+        self.error_at_current(format!("Expected 'end' to close '{}' block, got {:?}", construct, self.current_token));
+        false
+    }
+
     fn expect_peek(&mut self, tok: Token) -> bool {
-        if mem::discriminant(&self.peek_token) == mem::discriminant(&tok) { self.next_token(); true } 
-        else { self.errors.push(format!("Expected next token to be {:?}, got {:?} instead", tok, self.peek_token)); false }
+        if mem::discriminant(&self.peek_token) == mem::discriminant(&tok) { self.next_token(); true }
+        else { self.error_at_peek(format!("Expected next token to be {:?}, got {:?} instead", tok, self.peek_token)); false }
     }
     fn expect_peek_is_ident(&mut self) -> bool {
         if let Token::Ident(_) = self.peek_token { self.next_token(); true }
-        else { self.errors.push(format!("Expected next token to be an identifier, got {:?}", self.peek_token)); false }
+        else { self.error_at_peek(format!("Expected next token to be an identifier, got {:?}", self.peek_token)); false }
     }
 }
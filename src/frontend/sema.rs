@@ -1,66 +1,2019 @@
-use super::ast::*;
-use std::collections::HashMap;
+use super::{ast::*, diagnostic::Diagnostic, infer::Inference, span::Span, token::Token, types::{Bound, FloatType, IntType, Type}};
+use std::collections::{HashMap, HashSet};
+use std::mem;
 
-#[derive(Debug, Clone, PartialEq)]
-enum Type { Int, Bool, Function, Unknown }
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ScopeKind { Prelude, Module, Function, Block }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LoopKind { While, For, Repeat }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind { Local, Const, Param, LoopVar, Binding, Function, Variant, Builtin }
+
+impl SymbolKind {
+    fn is_assignable(self) -> bool { matches!(self, SymbolKind::Local | SymbolKind::Param) }
+}
+
+struct Symbol { ty: Type, span: Span, used: bool, kind: SymbolKind }
+
+/// A function literal being checked: the number of scopes outside of it, and
+/// the variables of those scopes it refers to, each with the position of its
+/// declaration.
+struct Closure { depth: usize, captures: Vec<(Capture, usize)> }
+
+struct Scope {
+    kind: ScopeKind, symbols: HashMap<String, Symbol>,
+    // Names declared later in this block, used to tell "used before declaration"
+    // apart from "undeclared".
+    upcoming: HashSet<String>,
+    // Variables of optional type known not to be nil in this scope, with the
+    // type they have here. Assigning to one of them ends its narrowing.
+    narrowed: HashMap<String, Type>,
+}
 
 pub struct SemanticAnalyzer {
-    symbol_table: HashMap<String, Type>,
+    scopes: Vec<Scope>,
+    infer: Inference,
+    // The return type of the function being checked, and whether it has a
+    // `return` statement so far.
+    return_type: Option<Type>, returned: bool,
+    // Enclosing loops of the statement being checked, innermost last.
+    loops: Vec<LoopKind>,
+    // The fields of every record type, in declaration order.
+    records: HashMap<String, Vec<(String, Type)>>,
+    // The variants of every enum type and the types of their fields, in
+    // declaration order.
+    enums: HashMap<String, Vec<(String, Vec<Type>)>>,
+    // The signatures of the methods declared for each type, by type name and
+    // then method name. `self` is not among their parameters.
+    methods: HashMap<String, HashMap<String, Type>>,
+    // The signatures of the methods of every interface, in declaration order
+    // and without `self`; and the records and enums implementing each, as
+    // (type name, interface name) pairs.
+    interfaces: HashMap<String, Vec<(String, Type)>>, implementations: HashSet<(String, String)>,
+    // The type parameters of every generic function and record, as
+    // `Type::Param`s, by name; and those of the declaration being checked,
+    // which are the ones its annotations may name.
+    generics: HashMap<String, Vec<Type>>, type_params: HashMap<String, Type>,
+    // Module-level variables some function assigns to, and variables some
+    // closure assigns to. They are never narrowed, since any call could set
+    // them to nil.
+    mutated_globals: HashSet<String>, mutated_captures: HashSet<String>,
+    // The names of the variables assigned to in the function being checked,
+    // or in the module's own code. Closures hold copies of the other
+    // variables they refer to.
+    assigned: HashSet<String>,
+    // The function literals being checked, innermost last.
+    closures: Vec<Closure>,
+    // Every checked function literal, by its position, with the declarations
+    // of the variables it shares by reference.
+    literals: Vec<(usize, Vec<usize>)>,
+    // Function literals that initialize a local and are only ever called
+    // through it, so that they cannot outlive it, by the position of the
+    // local's declaration.
+    local_closures: HashMap<usize, usize>,
+    // The declarations of the variables that closures which may outlive them
+    // share, which therefore have to live on the heap.
+    shared: HashSet<usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self { Self::new() }
 }
 
 impl SemanticAnalyzer {
     pub fn new() -> Self {
-        let mut symbols = HashMap::new();
-        symbols.insert("print".to_string(), Type::Function);
-        Self { symbol_table: symbols }
+        let mut prelude = Scope { kind: ScopeKind::Prelude, symbols: HashMap::new(), upcoming: HashSet::new(), narrowed: HashMap::new() };
+        let print = Type::Function { params: vec![Type::Unknown], ret: Box::new(Type::Void) };
+        prelude.symbols.insert("print".to_string(), Symbol { ty: print, span: Span::default(), used: true, kind: SymbolKind::Builtin });
+        // `error(e)` makes a failed `Result` and `pcall(f, ...)` calls `f`,
+        // catching runtime errors. Calls to both are checked specially.
+        for name in ["error", "pcall"] {
+            let builtin = Type::Function { params: vec![Type::Unknown], ret: Box::new(Type::Unknown) };
+            prelude.symbols.insert(name.to_string(), Symbol { ty: builtin, span: Span::default(), used: true, kind: SymbolKind::Builtin });
+        }
+        // Numeric type names double as explicit conversion functions: `f64(n)`.
+        for ty in IntType::ALL.into_iter().map(Type::Int).chain([Type::Float(FloatType::F32), Type::F64]) {
+            let conversion = Type::Function { params: vec![Type::Unknown], ret: Box::new(ty.clone()) };
+            prelude.symbols.insert(ty.to_string(), Symbol { ty: conversion, span: Span::default(), used: true, kind: SymbolKind::Builtin });
+        }
+        Self { scopes: vec![prelude], infer: Inference::default(), return_type: None, returned: false, loops: vec![], records: HashMap::new(), enums: HashMap::new(), methods: HashMap::new(),
+            interfaces: HashMap::new(), implementations: HashSet::new(), generics: HashMap::new(), type_params: HashMap::new(),
+            mutated_globals: HashSet::new(), mutated_captures: HashSet::new(), assigned: HashSet::new(), closures: vec![], literals: vec![],
+            local_closures: HashMap::new(), shared: HashSet::new(), diagnostics: vec![] }
+    }
+
+    /// Checks the whole program, filling in the types the backend needs, and
+    /// returns every error and warning found, in source order. The program may
+    /// only be compiled if none of them is an error.
+    pub fn analyze(&mut self, program: &mut Program) -> Vec<Diagnostic> {
+        self.push_scope(ScopeKind::Module, program);
+        self.declare_types(program);
+        // Declare every top-level function and method up front so calls may
+        // precede the definition and functions may be mutually recursive. Types
+        // that are not annotated start out as inference variables.
+        for stmt in program.iter_mut() {
+            if let StatementKind::Function { name, type_params, receiver, params, return_annotation, ret, .. } = &mut stmt.kind {
+                if !type_params.is_empty() {
+                    if receiver.is_some() {
+                        self.error("Methods cannot have type parameters", &type_params[0].span);
+                    } else {
+                        let generic = self.declare_type_params(type_params);
+                        self.generics.insert(name.clone(), generic);
+                        self.type_params = self.type_params_of(name);
+                    }
+                }
+                for param in params.iter_mut() {
+                    param.ty = self.resolve_annotation(&param.annotation).unwrap_or_else(|| self.infer.fresh());
+                }
+                *ret = self.resolve_return_annotation(return_annotation).unwrap_or_else(|| self.infer.fresh());
+                self.type_params.clear();
+                let sig = |params: &[Param]| Type::Function { params: params.iter().map(|p| p.ty.clone()).collect(), ret: Box::new(ret.clone()) };
+                match receiver {
+                    None => self.declare(name, sig(params), &stmt.span, SymbolKind::Function),
+                    Some(_) => self.declare_method(&params[0].ty, name, sig(&params[1..]), &stmt.span),
+                }
+            }
+        }
+        for stmt in program.iter() {
+            if let StatementKind::Record { name, interfaces, .. } | StatementKind::Enum { name, interfaces, .. } = &stmt.kind { self.check_implementations(name, interfaces); }
+        }
+        for stmt in program.iter() {
+            if let StatementKind::Function { body, .. } = &stmt.kind { Self::collect_assigned(body, &mut self.mutated_globals); }
+        }
+        for stmt in program.iter().filter(|stmt| !matches!(stmt.kind, StatementKind::Function { .. })) {
+            Self::collect_assigned(std::slice::from_ref(stmt), &mut self.assigned);
+        }
+        self.check_statements(program);
+        self.pop_scope();
+        // A variable shared with a closure that may outlive it has to outlive
+        // its own scope as well.
+        for (literal, by_reference) in mem::take(&mut self.literals) {
+            if self.escapes(literal) { self.shared.extend(by_reference); }
+        }
+        self.finalize_block(program);
+        let mut diagnostics = mem::take(&mut self.diagnostics);
+        // Unused locals are only reported when their scope ends.
+        diagnostics.sort_by_key(|diagnostic| diagnostic.span.byte_range.start);
+        diagnostics
+    }
+
+    /// Declares the records, enums and interfaces of the program before
+    /// anything else, since any signature may use them. Their names are all
+    /// known before their fields are resolved, so types may refer to each
+    /// other in any order. The variants of an enum become values: a variant
+    /// with fields is a constructor function, and one without is a value of
+    /// the enum.
+    fn declare_types(&mut self, program: &mut Program) {
+        let mut declared = HashSet::new();
+        // Interfaces come first, since they bound the type parameters of records.
+        let (interfaces, types): (Vec<_>, Vec<_>) = program.iter().partition(|stmt| matches!(stmt.kind, StatementKind::Interface { .. }));
+        for stmt in interfaces.into_iter().chain(types) {
+            let (StatementKind::Record { name, .. } | StatementKind::Enum { name, .. } | StatementKind::Interface { name, .. }) = &stmt.kind else { continue };
+            if Type::from_name(name).is_some() || self.scopes[0].symbols.contains_key(name) || name == "Result" {
+                self.error(format!("Cannot redefine builtin '{}'", name), &stmt.span);
+            } else if !declared.insert(name.clone()) {
+                self.error(format!("Type '{}' is already declared", name), &stmt.span);
+            } else if let StatementKind::Interface { .. } = &stmt.kind {
+                self.interfaces.insert(name.clone(), Vec::new());
+            } else if let StatementKind::Record { type_params, .. } = &stmt.kind {
+                self.records.insert(name.clone(), Vec::new());
+                if !type_params.is_empty() {
+                    let generic = self.declare_type_params(type_params);
+                    self.generics.insert(name.clone(), generic);
+                }
+            } else {
+                self.enums.insert(name.clone(), Vec::new());
+            }
+        }
+        for stmt in program.iter_mut() {
+            match &mut stmt.kind {
+                StatementKind::Record { name, fields, .. } => {
+                    self.type_params = self.type_params_of(name);
+                    let mut resolved: Vec<(String, Type)> = Vec::new();
+                    for field in fields.iter_mut() {
+                        field.ty = self.resolve_type(&field.annotation);
+                        if resolved.iter().any(|(other, _)| *other == field.name) {
+                            self.error(format!("Field '{}' is already declared in '{}'", field.name, name), &field.span);
+                        } else {
+                            resolved.push((field.name.clone(), field.ty.clone()));
+                        }
+                    }
+                    self.type_params.clear();
+                    // Only the first of several types of the same name is used.
+                    if declared.remove(name.as_str()) { self.records.insert(name.clone(), resolved); }
+                }
+                StatementKind::Enum { name, variants, .. } => {
+                    let first = declared.remove(name.as_str());
+                    let mut resolved = Vec::new();
+                    for variant in variants.iter_mut() {
+                        variant.field_types = variant.fields.iter().map(|field| self.resolve_type(field)).collect();
+                        if !first { continue; }
+                        let ty = Type::Enum(name.clone());
+                        let value = if variant.field_types.is_empty() { ty } else { Type::Function { params: variant.field_types.clone(), ret: Box::new(ty) } };
+                        self.declare(&variant.name, value, &variant.span, SymbolKind::Variant);
+                        resolved.push((variant.name.clone(), variant.field_types.clone()));
+                    }
+                    if first { self.enums.insert(name.clone(), resolved); }
+                }
+                StatementKind::Interface { name, methods } => {
+                    let first = declared.remove(name.as_str());
+                    let mut resolved: Vec<(String, Type)> = Vec::new();
+                    for method in methods.iter_mut() {
+                        let mut params = Vec::new();
+                        for param in &method.params {
+                            params.push(self.resolve_annotation(&param.annotation).unwrap_or_else(|| {
+                                self.error(format!("Parameter '{}' of interface method '{}:{}' needs a type annotation", param.name, name, method.name), &param.span)
+                            }));
+                        }
+                        let ret = self.resolve_return_annotation(&method.return_annotation).unwrap_or(Type::Void);
+                        method.ty = Type::Function { params, ret: Box::new(ret) };
+                        if resolved.iter().any(|(other, _)| *other == method.name) {
+                            self.error(format!("Method '{}:{}' is already declared", name, method.name), &method.span);
+                        } else {
+                            resolved.push((method.name.clone(), method.ty.clone()));
+                        }
+                    }
+                    if first { self.interfaces.insert(name.clone(), resolved); }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The type parameters `<T, U: Bound, ...>` of a generic declaration, as
+    /// `Type::Param`s.
+    fn declare_type_params(&mut self, type_params: &[TypeParam]) -> Vec<Type> {
+        let mut declared: Vec<Type> = Vec::new();
+        for param in type_params {
+            let bound = match param.bound.as_deref() {
+                None => None,
+                Some("Number") => Some(Bound::Number),
+                Some("Ordered") => Some(Bound::Ordered),
+                Some(interface) if self.interfaces.contains_key(interface) => Some(Bound::Interface(interface.to_string())),
+                Some(other) => { self.error(format!("Unknown bound '{}'; type parameters can be bound by Number, Ordered or an interface", other), &param.span); None }
+            };
+            if Type::from_name(&param.name).is_some() || self.records.contains_key(&param.name) || self.enums.contains_key(&param.name) || self.interfaces.contains_key(&param.name) {
+                self.error(format!("'{}' is already the name of a type", param.name), &param.span);
+            } else if declared.iter().any(|other| other.to_string() == param.name) {
+                self.error(format!("Type parameter '{}' is already declared", param.name), &param.span);
+            } else {
+                declared.push(Type::Param { name: param.name.clone(), bound });
+            }
+        }
+        declared
+    }
+
+    /// The type parameters of the generic declaration `name`, by their names.
+    fn type_params_of(&self, name: &str) -> HashMap<String, Type> {
+        self.generics.get(name).into_iter().flatten().map(|param| (param.to_string(), param.clone())).collect()
+    }
+
+    /// Gives the type parameters of the generic `name` fresh inference
+    /// variables, which number bounds restrict to numbers at once. Returns
+    /// them, with the substitution that applies them.
+    fn fresh_type_args(&mut self, name: &str, span: &Span) -> (Vec<Type>, HashMap<String, Type>) {
+        let (mut type_args, mut args) = (Vec::new(), HashMap::new());
+        for param in self.generics.get(name).cloned().unwrap_or_default() {
+            let Type::Param { name: param, bound } = param else { continue };
+            let arg = self.infer.fresh();
+            if bound == Some(Bound::Number) { self.infer.require_numeric(&arg, span); }
+            args.insert(param, arg.clone());
+            type_args.push(arg);
+        }
+        (type_args, args)
+    }
+
+    /// Reports the type arguments `args` of the generic `name` that do not
+    /// satisfy the bounds of its type parameters.
+    fn check_bounds(&mut self, name: &str, args: &[Type], span: &Span) {
+        for (param, arg) in self.generics.get(name).cloned().unwrap_or_default().iter().zip(args) {
+            let Type::Param { name: param, bound: Some(bound) } = param else { continue };
+            if !self.satisfies(arg, bound) { self.error(format!("Type {} does not satisfy the bound '{}: {}' of '{}'", arg, param, bound, name), span); }
+        }
+    }
+
+    /// Whether `ty` satisfies `bound`, or implements the interface it names.
+    fn satisfies(&self, ty: &Type, bound: &Bound) -> bool {
+        match (ty, bound) {
+            (Type::Record(name) | Type::Enum(name), Bound::Interface(interface)) => self.implementations.contains(&(name.clone(), interface.clone())),
+            (ty, bound) => ty.satisfies(bound),
+        }
+    }
+
+    /// Checks that the record or enum `name` implements the `interfaces` it
+    /// names: that it has a method of the same signature for each of theirs.
+    fn check_implementations(&mut self, name: &str, interfaces: &[TypeExpr]) {
+        for interface in interfaces {
+            let TypeExprKind::Named(interface_name) = &interface.kind else { continue };
+            let Some(required) = self.interfaces.get(interface_name).cloned() else {
+                self.error(format!("'{}' is not an interface", interface_name), &interface.span);
+                continue;
+            };
+            if !self.implementations.insert((name.to_string(), interface_name.clone())) {
+                self.error(format!("Type '{}' already implements '{}'", name, interface_name), &interface.span);
+                continue;
+            }
+            for (method, sig) in required {
+                match self.methods.get(name).and_then(|methods| methods.get(&method)).cloned() {
+                    None => { self.error(format!("Type '{}' does not implement '{}': it has no method '{}'", name, interface_name, method), &interface.span); }
+                    Some(found) if !self.infer.unify(&found, &sig, &interface.span) => {
+                        let found = self.infer.describe(&found);
+                        self.error(format!("Method '{}:{}' has type {}, but '{}' requires {}", name, method, found, interface_name, sig), &interface.span);
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+    }
+
+    /// The signature of method `name` of values of type `ty`, not counting
+    /// `self`: a builtin method, one declared for the type, or for a type
+    /// parameter or `dyn` value, one of its interface.
+    fn method_signature(&self, ty: &Type, name: &str) -> Option<Type> {
+        let interface_method = |interface: &String| self.interfaces.get(interface)?.iter().find(|(method, _)| method == name).map(|(_, sig)| sig.clone());
+        match ty {
+            Type::Dyn(interface) | Type::Param { bound: Some(Bound::Interface(interface)), .. } => interface_method(interface),
+            ty => ty.builtin_method(name).or_else(|| self.methods.get(&ty.to_string())?.get(name).cloned()),
+        }
+    }
+
+    /// Converts `expr`, of type `ty`, to `target` if `target` is a `dyn`
+    /// interface that `ty` implements. Returns whether it did.
+    fn try_dyn(&mut self, expr: &mut Expression, ty: &Type, target: &Type) -> bool {
+        let Type::Dyn(interface) = self.infer.shallow(target) else { return false };
+        let found = self.infer.resolve(ty);
+        if matches!(found, Type::Dyn(_)) || !self.satisfies(&found, &Bound::Interface(interface)) { return false; }
+        let span = expr.span.clone();
+        let value = mem::replace(expr, Expression::new(ExpressionKind::Nil, span.clone()));
+        *expr = Expression { kind: ExpressionKind::Dyn(Box::new(value)), span, ty: target.clone() };
+        true
+    }
+
+    /// Whether `name` names a generic function rather than a variable.
+    fn is_generic_function(&self, name: &str) -> bool {
+        self.symbol(name).is_some_and(|sym| sym.kind == SymbolKind::Function) && self.generics.contains_key(name)
+    }
+
+    /// An instance of the generic function `name` of type `ty`, named at
+    /// `span`, for types yet to be inferred, with the instance's type.
+    fn instantiate(&mut self, name: &str, ty: &Type, span: &Span) -> (ExpressionKind, Type) {
+        let (type_args, args) = self.fresh_type_args(name, span);
+        (ExpressionKind::Instance { name: name.to_string(), type_args }, ty.substitute(&args))
+    }
+
+    /// The enum that has a variant called `name`.
+    fn enum_of_variant(&self, name: &str) -> Option<String> {
+        self.enums.iter().find(|(_, variants)| variants.iter().any(|(variant, _)| variant == name)).map(|(e, _)| e.clone())
+    }
+
+    /// The type of field `name` of record `record`.
+    fn field_type(&self, record: &str, name: &str) -> Option<Type> {
+        self.records.get(record)?.iter().find(|(field, _)| field == name).map(|(_, ty)| ty.clone())
+    }
+
+    /// Adds method `name` to the method table of `receiver`.
+    fn declare_method(&mut self, receiver: &Type, name: &str, sig: Type, span: &Span) {
+        // The receiver's type name was already reported if it is unknown.
+        if *receiver == Type::Error { return; }
+        if receiver.builtin_method(name).is_some() {
+            self.error(format!("Cannot redefine builtin method '{}:{}'", receiver, name), span);
+            return;
+        }
+        let methods = self.methods.entry(receiver.to_string()).or_default();
+        if methods.insert(name.to_string(), sig).is_some() {
+            self.error(format!("Method '{}:{}' is already declared", receiver, name), span);
+        }
+    }
+
+    /// Checks the arguments of a call to the function or method (`kind`)
+    /// `name` against its parameter types. Returns whether their number was right.
+    fn check_arguments(&mut self, kind: &str, name: &str, params: &[Type], arguments: &mut [Expression], span: &Span) -> bool {
+        if params.len() != arguments.len() {
+            for arg in arguments.iter_mut() { self.check_expression(arg); }
+            self.error(format!("{} '{}' expects {} argument(s), got {}", kind, name, params.len(), arguments.len()), span);
+            return false;
+        }
+        for (param, arg) in params.iter().zip(arguments.iter_mut()) { self.expect_assignable(arg, param, &format!("Argument to '{}'", name)); }
+        true
+    }
+
+    /// Explains the errors reported since `reported` about `arguments` passed
+    /// to `name` for parameters without a type annotation: such a parameter
+    /// has a single type, which its first uses decided, even where a generic
+    /// function would accept several.
+    fn explain_inferred_params(&mut self, name: &str, params: &[Type], arguments: &[Expression], reported: usize) {
+        for (param, arg) in params.iter().zip(arguments) {
+            if !matches!(param, Type::Var(_)) { continue; }
+            let Some(diagnostic) = self.diagnostics[reported..].iter_mut().rfind(|d| d.is_error() && d.span == arg.span) else { continue };
+            diagnostic.message.push_str(&format!("; this parameter of '{}' has no type annotation, so it takes the same type in every call, unlike one of a generic function", name));
+        }
+    }
+
+    /// Checks `error(e)`, a failed `Result` with error `e`. Its ok type is
+    /// settled by where it is used.
+    fn check_error_call(&mut self, arguments: &mut [Expression], span: &Span) -> Type {
+        let err = self.infer.fresh();
+        if !self.check_arguments("Function", "error", std::slice::from_ref(&err), arguments, span) { return Type::Error; }
+        if self.infer.resolve(&err) == Type::Void { return self.error("The error given to 'error' does not produce a value", &arguments[0].span); }
+        Type::Result { ok: Box::new(self.infer.fresh()), err: Box::new(err) }
+    }
+
+    /// Checks `pcall(f, ...)`, which calls the function `f` with the other
+    /// arguments. Its value is a `Result` of what `f` returns, or the message
+    /// of the runtime error that stopped it.
+    fn check_pcall(&mut self, arguments: &mut [Expression], span: &Span) -> Type {
+        let Some((function, arguments)) = arguments.split_first_mut() else {
+            return self.error("'pcall' expects a function to call, as in pcall(f, ...)", span);
+        };
+        let ty = self.check_expression(function);
+        let name = match &function.kind {
+            ExpressionKind::Identifier(name) if self.resolve(name, span).is_ok_and(|sym| sym.kind == SymbolKind::Function) => name.clone(),
+            ExpressionKind::Instance { name, .. } => name.clone(),
+            _ => {
+                for arg in arguments.iter_mut() { self.check_expression(arg); }
+                return self.error("The first argument to 'pcall' must name a function declared with 'function'", &function.span);
+            }
+        };
+        let Type::Function { params, ret } = self.infer.shallow(&ty) else { unreachable!("functions have function types") };
+        self.check_arguments("Function", &name, &params, arguments, span);
+        Type::Result { ok: ret, err: Box::new(Type::String) }
+    }
+
+    /// How the callee `expr` is referred to in diagnostics: its source text,
+    /// with arguments and indexes elided.
+    fn callee_name(expr: &Expression) -> String {
+        match &expr.kind {
+            ExpressionKind::Identifier(name) => name.clone(),
+            ExpressionKind::Field { target, name } => format!("{}.{}", Self::callee_name(target), name),
+            ExpressionKind::Index { target, .. } => format!("{}[...]", Self::callee_name(target)),
+            ExpressionKind::Call { function, .. } => format!("{}(...)", Self::callee_name(function)),
+            ExpressionKind::FunctionLiteral { .. } => "function".to_string(),
+            _ => "...".to_string(),
+        }
+    }
+
+    /// Records an error and returns the type standing in for whatever failed.
+    fn report(&mut self, error: Diagnostic) -> Type {
+        self.diagnostics.push(error);
+        Type::Error
+    }
+
+    fn error(&mut self, message: impl Into<String>, span: &Span) -> Type { self.report(Diagnostic::error(message, span.clone())) }
+
+    fn warning(&mut self, message: impl Into<String>, span: &Span) { self.diagnostics.push(Diagnostic::warning(message, span.clone())); }
+
+    fn push_scope(&mut self, kind: ScopeKind, block: &BlockStatement) {
+        let upcoming = block.iter().filter_map(|stmt| match &stmt.kind { StatementKind::Let { vars, .. } => Some(vars.iter().map(|var| var.name.clone())), _ => None }).flatten().collect();
+        self.scopes.push(Scope { kind, symbols: HashMap::new(), upcoming, narrowed: HashMap::new() });
+    }
+
+    fn pop_scope(&mut self) {
+        let Some(scope) = self.scopes.pop() else { return };
+        // Only `local` and `const` bindings are reported; parameters, loop
+        // variables and functions are not.
+        let mut unused: Vec<_> = scope.symbols.into_iter()
+            .filter(|(name, sym)| matches!(sym.kind, SymbolKind::Local | SymbolKind::Const) && !sym.used && !name.starts_with('_'))
+            .collect();
+        unused.sort_by_key(|(_, sym)| sym.span.byte_range.start);
+        for (name, sym) in unused {
+            self.warning(format!("Local variable '{}' is declared but never used", name), &sym.span);
+        }
+    }
+
+    fn in_function(&self) -> bool { self.scopes.iter().any(|s| s.kind == ScopeKind::Function) }
+
+    /// Adds `name` to the innermost scope. Shadowing a binding from an enclosing
+    /// scope is allowed, with a warning; redeclaring a name within the same
+    /// scope is not.
+    fn declare(&mut self, name: &str, ty: Type, span: &Span, kind: SymbolKind) {
+        // The backend recognizes builtins by name, so they cannot be shadowed.
+        if self.scopes[0].symbols.contains_key(name) {
+            self.error(format!("Cannot redefine builtin '{}'", name), span);
+            return;
+        }
+        // Constructors are written with the record's name, as in `Point { x = 1 }`,
+        // and in C a variable would hide the type's name.
+        if self.records.contains_key(name) || self.enums.contains_key(name) {
+            self.error(format!("'{}' is already the name of a type", name), span);
+            return;
+        }
+        if self.scopes.last().expect("scope stack is never empty").symbols.contains_key(name) {
+            self.error(format!("'{}' is already declared in this scope", name), span);
+            return;
+        }
+        if let Some(outer) = self.scopes.iter().rev().find_map(|scope| scope.symbols.get(name)).filter(|_| !name.starts_with('_')) {
+            let message = format!("'{}' shadows the declaration at line {}", name, outer.span.line);
+            self.warning(message, span);
+        }
+        let scope = self.scopes.last_mut().expect("scope stack is never empty");
+        scope.upcoming.remove(name);
+        scope.symbols.insert(name.to_string(), Symbol { ty, span: span.clone(), used: false, kind });
+    }
+
+    /// Resolves `name` from the innermost scope outwards and marks it as used.
+    fn lookup(&mut self, name: &str, span: &Span) -> Result<Type, Diagnostic> {
+        let sym = self.resolve(name, span)?;
+        sym.used = true;
+        Ok(sym.ty.clone())
+    }
+
+    /// Resolves `name` like `lookup`, without marking it as used. A variable
+    /// of an enclosing function becomes a capture of the closures in between.
+    fn resolve(&mut self, name: &str, span: &Span) -> Result<&mut Symbol, Diagnostic> {
+        if let Some(depth) = self.scopes.iter().rposition(|scope| scope.symbols.contains_key(name)) {
+            let sym = &self.scopes[depth].symbols[name];
+            // Module-level variables outlive every closure and need no capturing.
+            if depth > 1 && !matches!(sym.kind, SymbolKind::Function | SymbolKind::Variant | SymbolKind::Builtin) {
+                let decl = sym.span.byte_range.start;
+                let capture = Capture { name: name.to_string(), ty: sym.ty.clone(), by_reference: sym.kind.is_assignable() && self.assigned.contains(name) };
+                let mut captured = false;
+                for closure in self.closures.iter_mut().filter(|closure| depth < closure.depth) {
+                    if !closure.captures.iter().any(|(other, _)| other.name == name) { closure.captures.push((capture.clone(), decl)); }
+                    captured = true;
+                }
+                // A closure's copy of another closure may be called anywhere.
+                if captured { self.local_closures.remove(&decl); }
+            }
+            return Ok(self.scopes[depth].symbols.get_mut(name).expect("symbol was just found"));
+        }
+        if self.scopes.iter().any(|scope| scope.upcoming.contains(name)) {
+            return Err(Diagnostic::error(format!("Variable '{}' is used before its declaration", name), span.clone()));
+        }
+        Err(Diagnostic::error(format!("Undeclared variable: {}", name), span.clone()))
+    }
+
+    /// The symbol `name` refers to here.
+    fn symbol(&self, name: &str) -> Option<&Symbol> { self.scopes.iter().rev().find_map(|scope| scope.symbols.get(name)) }
+
+    /// The type variable `name` is narrowed to here, if it is known not to be
+    /// nil. A function may run at any time, so narrowings from outside of it
+    /// do not apply inside.
+    fn narrowed(&self, name: &str) -> Option<Type> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(ty) = scope.narrowed.get(name) { return Some(ty.clone()); }
+            if scope.symbols.contains_key(name) || scope.kind == ScopeKind::Function { return None; }
+        }
+        None
+    }
+
+    /// Whether the function literal at `literal` may outlive the block it is
+    /// created in.
+    fn escapes(&self, literal: usize) -> bool { !self.local_closures.values().any(|local| *local == literal) }
+
+    /// Narrows variables in the innermost scope, except module-level ones
+    /// that functions assign to.
+    fn narrow(&mut self, narrowings: Vec<(String, Type)>) {
+        for (name, ty) in narrowings {
+            let global = self.scopes.iter().rposition(|scope| scope.symbols.contains_key(&name)) == Some(1);
+            if (global && self.mutated_globals.contains(&name)) || self.mutated_captures.contains(&name) { continue; }
+            self.scopes.last_mut().expect("scope stack is never empty").narrowed.insert(name, ty);
+        }
     }
 
-    pub fn analyze(&mut self, program: &mut Program) -> Result<(), String> {
-        for stmt in program { self.check_statement(stmt)?; }
-        Ok(())
+    /// Ends the narrowing of variable `name`, which is being assigned to.
+    fn forget(&mut self, name: &str) {
+        for scope in self.scopes.iter_mut() { scope.narrowed.remove(name); }
+    }
+
+    /// The variables of optional type that checked `condition` proves are not
+    /// nil when it is `when`, with their types without the `?`: `x ~= nil`
+    /// when it holds, `x == nil` when it does not, and combinations of these
+    /// with `and`, `or` and `not`.
+    fn narrowings(&self, condition: &Expression, when: bool) -> Vec<(String, Type)> {
+        match &condition.kind {
+            ExpressionKind::Infix { op: op @ (Token::Eq | Token::NotEq), left, right } if (*op == Token::NotEq) == when => {
+                let operand = match (&left.kind, &right.kind) { (_, ExpressionKind::Nil) => left, (ExpressionKind::Nil, _) => right, _ => return vec![] };
+                match (&operand.kind, self.infer.resolve(&operand.ty)) {
+                    (ExpressionKind::Identifier(name), Type::Optional(inner)) => vec![(name.clone(), *inner)],
+                    _ => vec![],
+                }
+            }
+            ExpressionKind::Infix { op: Token::And, left, right } if when => [self.narrowings(left, when), self.narrowings(right, when)].concat(),
+            ExpressionKind::Infix { op: Token::Or, left, right } if !when => [self.narrowings(left, when), self.narrowings(right, when)].concat(),
+            ExpressionKind::Prefix { op: Token::Not, right } => self.narrowings(right, !when),
+            _ => vec![],
+        }
     }
 
-    fn check_statement(&mut self, stmt: &Statement) -> Result<Type, String> {
-        match stmt {
-            Statement::Let { name, value } => {
-                let val_type = self.check_expression(value)?;
-                self.symbol_table.insert(name.clone(), val_type);
-                Ok(Type::Unknown)
+    /// Adds the names of the variables `block` assigns to, including in nested
+    /// blocks, to `names`.
+    fn collect_assigned(block: &[Statement], names: &mut HashSet<String>) {
+        for stmt in block {
+            match &stmt.kind {
+                StatementKind::Assign { target, value, .. } => {
+                    if let ExpressionKind::Identifier(name) = &target.kind { names.insert(name.clone()); }
+                    Self::collect_assigned_in(target, names);
+                    Self::collect_assigned_in(value, names);
+                }
+                StatementKind::Let { values, .. } => for value in values { Self::collect_assigned_in(value, names); },
+                StatementKind::Expression(expr) => Self::collect_assigned_in(expr, names),
+                StatementKind::Return(values) => for value in values { Self::collect_assigned_in(value, names); },
+                StatementKind::ForIn { iterable, body, .. } => {
+                    Self::collect_assigned_in(iterable, names);
+                    Self::collect_assigned(body, names);
+                }
+                StatementKind::Function { body, .. } => Self::collect_assigned(body, names),
+                StatementKind::While { condition, body } | StatementKind::Repeat { body, condition } => {
+                    Self::collect_assigned_in(condition, names);
+                    Self::collect_assigned(body, names);
+                }
+                StatementKind::NumericFor { start, stop, step, body, .. } => {
+                    for bound in [Some(start), Some(stop), step.as_ref()].into_iter().flatten() { Self::collect_assigned_in(bound, names); }
+                    Self::collect_assigned(body, names);
+                }
+                StatementKind::Record { .. } | StatementKind::Enum { .. } | StatementKind::Interface { .. } | StatementKind::Break | StatementKind::Continue => {}
             }
-            Statement::Return(expr) => self.check_expression(expr),
-            Statement::Expression(expr) => self.check_expression(expr),
         }
     }
 
-    fn check_expression(&mut self, expr: &Expression) -> Result<Type, String> {
-        match expr {
-            Expression::IntegerLiteral(_) => Ok(Type::Int),
-            Expression::Boolean(_) => Ok(Type::Bool),
-            Expression::Identifier(name) => self.symbol_table.get(name).cloned().ok_or_else(|| format!("Undeclared variable: {}", name)),
-            Expression::Infix { op:_, left, right } => {
-                let left_type = self.check_expression(left)?;
-                let right_type = self.check_expression(right)?;
-                if left_type != Type::Int || right_type != Type::Int {
-                    return Err(format!("Cannot perform arithmetic on non-integers. Left is {:?}, Right is {:?}", left_type, right_type));
+    /// Like `collect_assigned`, for the blocks within `expr`.
+    fn collect_assigned_in(expr: &Expression, names: &mut HashSet<String>) {
+        match &expr.kind {
+            ExpressionKind::Prefix { right: inner, .. } | ExpressionKind::Field { target: inner, .. } | ExpressionKind::Try(inner) | ExpressionKind::Wrap(inner)
+            | ExpressionKind::Unwrap(inner) | ExpressionKind::Dyn(inner) => Self::collect_assigned_in(inner, names),
+            ExpressionKind::Infix { left, right, .. } | ExpressionKind::Index { target: left, index: right } => {
+                Self::collect_assigned_in(left, names);
+                Self::collect_assigned_in(right, names);
+            }
+            ExpressionKind::If { condition, consequence, alternative } => {
+                Self::collect_assigned_in(condition, names);
+                Self::collect_assigned(consequence, names);
+                if let Some(alt) = alternative { Self::collect_assigned(alt, names); }
+            }
+            ExpressionKind::FunctionLiteral { body, .. } => Self::collect_assigned(body, names),
+            ExpressionKind::Call { function: first, arguments } | ExpressionKind::MethodCall { receiver: first, arguments, .. } => {
+                Self::collect_assigned_in(first, names);
+                for arg in arguments { Self::collect_assigned_in(arg, names); }
+            }
+            ExpressionKind::ArrayLiteral(elements) => for element in elements { Self::collect_assigned_in(element, names); },
+            ExpressionKind::Slice { target, start, stop } => for part in [target, start, stop] { Self::collect_assigned_in(part, names); },
+            ExpressionKind::RecordLiteral { fields, .. } => for field in fields { Self::collect_assigned_in(&field.value, names); },
+            ExpressionKind::Match { subject, arms } => {
+                Self::collect_assigned_in(subject, names);
+                for arm in arms {
+                    if let Some(guard) = &arm.guard { Self::collect_assigned_in(guard, names); }
+                    Self::collect_assigned(&arm.body, names);
                 }
-                Ok(Type::Int)
+            }
+            ExpressionKind::Identifier(_) | ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_) | ExpressionKind::StringLiteral(_)
+            | ExpressionKind::Boolean(_) | ExpressionKind::Nil | ExpressionKind::Instance { .. } => {}
+        }
+    }
+
+    /// Ends the narrowing of the variables a loop assigns to, since the
+    /// assignment may happen before any part of the loop runs again.
+    fn forget_assigned(&mut self, loop_stmt: &Statement) {
+        let mut names = HashSet::new();
+        Self::collect_assigned(std::slice::from_ref(loop_stmt), &mut names);
+        for name in names { self.forget(&name); }
+    }
+
+    fn resolve_type(&mut self, ty: &TypeExpr) -> Type {
+        match &ty.kind {
+            TypeExprKind::Named(name) if self.type_params.contains_key(name) => self.type_params[name].clone(),
+            TypeExprKind::Named(name) if self.generics.contains_key(name) => {
+                let params: Vec<String> = self.generics[name].iter().map(ToString::to_string).collect();
+                self.error(format!("Type '{}' needs type arguments, as in {}<{}>", name, name, params.join(", ")), &ty.span)
+            }
+            TypeExprKind::Named(name) if self.records.contains_key(name) => Type::Record(name.clone()),
+            TypeExprKind::Named(name) if self.enums.contains_key(name) => Type::Enum(name.clone()),
+            TypeExprKind::Named(name) if self.interfaces.contains_key(name) => {
+                self.error(format!("'{}' is an interface; a value of any type implementing it has type 'dyn {}'", name, name), &ty.span)
+            }
+            TypeExprKind::Named(name) if name == "Result" => self.error("Type 'Result' needs an ok and an error type, as in Result<i64, string>", &ty.span),
+            TypeExprKind::Named(name) => Type::from_name(name).unwrap_or_else(|| self.error(format!("Unknown type '{}'", name), &ty.span)),
+            TypeExprKind::Array { elem, size } => Type::Array { elem: Box::new(self.resolve_type(elem)), size: *size },
+            TypeExprKind::Slice(elem) => Type::Slice(Box::new(self.resolve_type(elem))),
+            TypeExprKind::Optional(inner) => match self.resolve_type(inner) {
+                inner @ Type::Optional(_) => self.error(format!("Type {} is already optional", inner), &ty.span),
+                inner => Type::Optional(Box::new(inner)),
             },
-            Expression::If { condition, consequence, .. } => {
-                if self.check_expression(condition)? != Type::Bool {
-                    return Err("If condition must be a boolean".to_string());
+            TypeExprKind::Function { params, ret } => {
+                let params = params.iter().map(|param| self.resolve_type(param)).collect();
+                let ret = ret.as_ref().map_or(Type::Void, |ret| self.resolve_return_type(ret));
+                Type::Function { params, ret: Box::new(ret) }
+            }
+            TypeExprKind::Generic { name, args } => {
+                let args: Vec<_> = args.iter().map(|arg| self.resolve_type(arg)).collect();
+                match (name.as_str(), &args[..]) {
+                    ("Result", [ok, err]) => Type::Result { ok: Box::new(ok.clone()), err: Box::new(err.clone()) },
+                    ("Result", _) => self.error(format!("Type 'Result' expects 2 type arguments, got {}", args.len()), &ty.span),
+                    (name, args) if self.records.contains_key(name) && self.generics.contains_key(name) => {
+                        let expected = self.generics[name].len();
+                        if args.len() != expected { return self.error(format!("Type '{}' expects {} type argument(s), got {}", name, expected, args.len()), &ty.span); }
+                        self.check_bounds(name, args, &ty.span);
+                        Type::Generic { name: name.to_string(), args: args.to_vec() }
+                    }
+                    _ => self.error(format!("Type '{}' does not take type arguments", name), &ty.span),
+                }
+            }
+            TypeExprKind::Tuple(elems) if elems.len() == 1 => self.resolve_type(&elems[0]),
+            TypeExprKind::Tuple(_) => self.error("Only a function can have several values, as its return type", &ty.span),
+            TypeExprKind::Dyn(name) if self.interfaces.contains_key(name) => Type::Dyn(name.clone()),
+            TypeExprKind::Dyn(name) => self.error(format!("Unknown interface '{}'", name), &ty.span),
+        }
+    }
+
+    /// Like `resolve_type`, for the return type of a function, which may be
+    /// several values.
+    fn resolve_return_type(&mut self, ty: &TypeExpr) -> Type {
+        match &ty.kind {
+            TypeExprKind::Tuple(elems) if elems.len() > 1 => Type::Tuple(elems.iter().map(|elem| self.resolve_type(elem)).collect()),
+            _ => self.resolve_type(ty),
+        }
+    }
+
+    fn resolve_annotation(&mut self, annotation: &Option<TypeExpr>) -> Option<Type> {
+        annotation.as_ref().map(|ty| self.resolve_type(ty))
+    }
+
+    fn resolve_return_annotation(&mut self, annotation: &Option<TypeExpr>) -> Option<Type> {
+        annotation.as_ref().map(|ty| self.resolve_return_type(ty))
+    }
+
+    /// Checks a sequence of statements. The first statement following a
+    /// `return`, `break` or `continue` can never run and is reported. After
+    /// an `if` one of whose branches always jumps away, the variables the
+    /// condition narrows for the other branch stay narrowed.
+    fn check_statements(&mut self, statements: &mut [Statement]) {
+        let (mut jump, mut warned) = (None, false);
+        for stmt in statements.iter_mut() {
+            if let (Some(keyword), false) = (jump, warned) {
+                self.warning(format!("Unreachable code after '{}'", keyword), &stmt.span);
+                warned = true;
+            }
+            self.check_statement(stmt);
+            if let StatementKind::Expression(Expression { kind: ExpressionKind::If { condition, consequence, alternative }, .. }) = &stmt.kind {
+                match alternative {
+                    _ if Self::jumps_away(consequence) => self.narrow(self.narrowings(condition, false)),
+                    Some(alternative) if Self::jumps_away(alternative) => self.narrow(self.narrowings(condition, true)),
+                    _ => {}
                 }
-                for stmt in consequence { self.check_statement(stmt)?; }
-                Ok(Type::Unknown)
+            }
+            jump = jump.or(Self::jump_keyword(stmt));
+        }
+    }
+
+    fn jump_keyword(stmt: &Statement) -> Option<&'static str> {
+        match stmt.kind {
+            StatementKind::Return(_) => Some("return"),
+            StatementKind::Break => Some("break"),
+            StatementKind::Continue => Some("continue"),
+            _ => None,
+        }
+    }
+
+    /// Whether `block` always ends in a `return`, `break` or `continue`.
+    fn jumps_away(block: &BlockStatement) -> bool { block.last().and_then(Self::jump_keyword).is_some() }
+
+    fn check_block(&mut self, block: &mut BlockStatement) { self.check_narrowed_block(block, Vec::new()); }
+
+    /// Checks a block in which `narrowings` apply.
+    fn check_narrowed_block(&mut self, block: &mut BlockStatement, narrowings: Vec<(String, Type)>) {
+        self.push_scope(ScopeKind::Block, block);
+        self.narrow(narrowings);
+        self.check_statements(block);
+        self.pop_scope();
+    }
+
+    /// Checks a branch of an `if` used as a value, in which `narrowings`
+    /// apply. The branch's last statement must be an expression, which
+    /// produces the value.
+    fn check_value_block(&mut self, block: &mut BlockStatement, span: &Span, narrowings: Vec<(String, Type)>) -> Type {
+        self.push_scope(ScopeKind::Block, block);
+        self.narrow(narrowings);
+        let ty = self.check_value_block_statements(block, span);
+        self.pop_scope();
+        ty
+    }
+
+    fn check_value_block_statements(&mut self, block: &mut BlockStatement, span: &Span) -> Type {
+        let Some((last, init)) = block.split_last_mut() else {
+            return self.error("An 'if' used as a value needs a value in each branch", span);
+        };
+        self.check_statements(init);
+        let StatementKind::Expression(value) = &mut last.kind else {
+            self.check_statement(last);
+            return self.error("This branch of an 'if' used as a value must end in an expression", &last.span);
+        };
+        let ty = self.check_expression(value);
+        if self.infer.resolve(&ty) == Type::Void {
+            return self.error("This expression does not produce a value for the 'if'", &value.span);
+        }
+        ty
+    }
+
+    /// The expression producing the value of a branch checked by `check_value_block`.
+    fn block_value(block: &mut BlockStatement) -> Option<&mut Expression> {
+        match block.last_mut() {
+            Some(Statement { kind: StatementKind::Expression(expr), .. }) => Some(expr),
+            _ => None,
+        }
+    }
+
+    fn check_loop_body(&mut self, kind: LoopKind, body: &mut BlockStatement, narrowings: Vec<(String, Type)>) {
+        self.loops.push(kind);
+        self.check_narrowed_block(body, narrowings);
+        self.loops.pop();
+    }
+
+    fn expect_type(&mut self, expr: &mut Expression, expected: Type, what: &str) {
+        let ty = self.check_expression(expr);
+        self.expect_checked_type(expr, &ty, expected, what);
+    }
+
+    fn expect_checked_type(&mut self, expr: &mut Expression, ty: &Type, expected: Type, what: &str) {
+        if !self.infer.unify(ty, &expected, &expr.span) {
+            let note = self.inferred_note(ty, &expr.span);
+            self.error(format!("{} must be {}, got {}{}", what, expected, self.infer.describe(ty), note), &expr.span);
+        }
+    }
+
+    /// Checks that `expr` can be used where a `target` is expected, applying
+    /// literal typing, implicit widening conversions, the conversion of a
+    /// value to its optional type, and that of a record or enum to a `dyn`
+    /// interface it implements.
+    fn expect_assignable(&mut self, expr: &mut Expression, target: &Type, what: &str) {
+        if self.expect_array_literal(expr, target) { return; }
+        let ty = self.check_expression(expr);
+        self.expect_checked_assignable(expr, &ty, target, what);
+    }
+
+    fn expect_checked_assignable(&mut self, expr: &mut Expression, ty: &Type, target: &Type, what: &str) {
+        let ty = ty.clone();
+        if self.coerce_literal(expr, target) || self.try_wrap(expr, &ty, target) || self.try_dyn(expr, &ty, target) { return; }
+        let (found, expected) = (self.infer.resolve(&ty), self.infer.resolve(target));
+        if found.converts_to(&expected) || self.infer.unify(&found, &expected, &expr.span) { return; }
+        // An array is also a slice of all of its elements.
+        if let (Type::Array { elem, .. }, Type::Slice(target_elem)) = (&found, &expected) {
+            if self.infer.unify(elem, target_elem, &expr.span) { return; }
+        }
+        if let (ExpressionKind::IntegerLiteral(value), Type::Int(_)) = (&expr.kind, &expected) {
+            self.error(format!("Integer literal {} does not fit in {}", value, expected), &expr.span);
+            return;
+        }
+        if let (Type::Record(name) | Type::Enum(name), Type::Dyn(interface)) = (&found, &expected) {
+            self.error(format!("{}: expected {}, found {}, which does not implement '{}'", what, expected, name, interface), &expr.span);
+            return;
+        }
+        if expr.kind == ExpressionKind::Nil {
+            self.error(format!("{}: expected {}, found nil; only optional types such as {}? can be nil", what, expected, expected), &expr.span);
+            return;
+        }
+        let note = [target, &ty].into_iter().map(|ty| self.inferred_note(ty, &expr.span)).find(|note| !note.is_empty()).unwrap_or_default();
+        self.error(format!("{}: expected {}, found {}{}", what, self.infer.describe(target), self.infer.describe(&ty), note), &expr.span);
+    }
+
+    /// Converts `expr`, of type `ty`, to `target` if `target` is an optional, or
+    /// a `Result` with an ok type, of a type `ty` is assignable to. Returns
+    /// whether it did.
+    fn try_wrap(&mut self, expr: &mut Expression, ty: &Type, target: &Type) -> bool {
+        let (target_kind, found) = (self.infer.shallow(target), self.infer.shallow(ty));
+        let (Type::Optional(inner) | Type::Result { ok: inner, .. }) = &target_kind else { return false };
+        if matches!(found, Type::Var(_) | Type::Unknown | Type::Error) { return false; }
+        // A result is only taken for the ok value of another if it is of that
+        // type exactly, rather than one that could still become the target.
+        if mem::discriminant(&found) == mem::discriminant(&target_kind) && self.infer.resolve(ty) != self.infer.resolve(inner) { return false; }
+        let inner = inner.clone();
+        let fits = self.coerce_literal(expr, &inner) || self.infer.resolve(ty).converts_to(&self.infer.resolve(&inner)) || self.infer.unify(ty, &inner, &expr.span);
+        if !fits { return false; }
+        let span = expr.span.clone();
+        let value = mem::replace(expr, Expression::new(ExpressionKind::Nil, span.clone()));
+        *expr = match value.kind {
+            // A narrowed variable is used as it is.
+            ExpressionKind::Unwrap(variable) if self.infer.resolve(&variable.ty) == self.infer.resolve(target) => *variable,
+            kind => Expression { kind: ExpressionKind::Wrap(Box::new(Expression { kind, ..value })), span, ty: target.clone() },
+        };
+        true
+    }
+
+    /// Reports the use of a value of optional type `ty`, which may be nil, in
+    /// a way that needs a value: "Cannot {action} a value of type T?...".
+    fn possibly_nil(&mut self, action: &str, ty: &Type, span: &Span) -> Type {
+        self.error(format!("Cannot {} a value of type {} that may be nil; check that it is not nil first", action, self.infer.describe(ty)), span)
+    }
+
+    /// Checks an array literal against the array or slice type it initializes,
+    /// so that its elements are typed by that type rather than by themselves.
+    /// Returns whether `expr` was such a literal.
+    fn expect_array_literal(&mut self, expr: &mut Expression, target: &Type) -> bool {
+        let ExpressionKind::ArrayLiteral(elements) = &mut expr.kind else { return false };
+        let (elem, size) = match self.infer.shallow(target) {
+            Type::Array { elem, size } => (*elem, size),
+            Type::Slice(elem) => (*elem, None),
+            _ => return false,
+        };
+        if let Some(size) = size.filter(|size| *size != elements.len() as u64) {
+            self.error(format!("Expected {} array elements, found {}", size, elements.len()), &expr.span);
+        }
+        for element in elements.iter_mut() { self.expect_assignable(element, &elem, "Array element"); }
+        expr.ty = Type::Array { elem: Box::new(elem), size };
+        true
+    }
+
+    /// Points at the line a type was inferred from, when that is not the line
+    /// of the expression being reported.
+    fn inferred_note(&self, ty: &Type, span: &Span) -> String {
+        match self.infer.inferred_at(ty) {
+            Some(at) if at.line != span.line => format!(" (inferred at line {})", at.line),
+            _ => String::new(),
+        }
+    }
+
+    /// Number literals have no fixed type of their own: an integer literal takes
+    /// on any integer type it fits in (or any float type), and a float literal
+    /// any float type. Returns whether `expr` was such a literal and was retyped.
+    fn coerce_literal(&mut self, expr: &mut Expression, target: &Type) -> bool {
+        let target = self.infer.shallow(target);
+        let fits = match (&mut expr.kind, &target) {
+            (ExpressionKind::IntegerLiteral(value), Type::Int(int)) => Self::literal_fits(value, *int),
+            (ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_), Type::Float(_)) => true,
+            // An integer literal leaves the type open for later uses to decide.
+            (ExpressionKind::IntegerLiteral(_), Type::Var(_)) => self.infer.require_numeric(&target, &expr.span),
+            (ExpressionKind::FloatLiteral(_), Type::Var(_)) => self.infer.unify(&target, &Type::F64, &expr.span),
+            _ => false,
+        };
+        if fits { expr.ty = target; }
+        fits
+    }
+
+    /// Whether the integer literal `value` fits in `int`. As in Lua, one above
+    /// `i64::MAX`, which only a hexadecimal literal can be, is also an `i64`
+    /// with the same bits, and `value` becomes that negative number.
+    fn literal_fits(value: &mut i128, int: IntType) -> bool {
+        if int == IntType::I64 && (i128::from(i64::MAX) + 1..=i128::from(u64::MAX)).contains(value) { *value = i128::from(*value as u64 as i64); }
+        int.contains(*value)
+    }
+
+    /// Checks arithmetic between two operands and returns the type it is carried out in.
+    fn check_arithmetic(&mut self, left: &mut Expression, right: &mut Expression, span: &Span) -> Type {
+        let left_type = self.check_expression(left);
+        let right_type = self.check_expression(right);
+        self.numeric_operands(left, right, left_type, right_type, span, "perform arithmetic on")
+    }
+
+    /// Finds the common type of two already checked number operands, which
+    /// `action` (as in "Cannot {action} bool and i64") is applied to.
+    fn numeric_operands(&mut self, left: &mut Expression, right: &mut Expression, left_type: Type, right_type: Type, span: &Span, action: &str) -> Type {
+        if !self.infer.require_numeric(&left_type, span) || !self.infer.require_numeric(&right_type, span) {
+            if let Some(optional) = [&left_type, &right_type].into_iter().find(|ty| matches!(self.infer.shallow(ty), Type::Optional(_))).cloned() {
+                return self.possibly_nil(action, &optional, span);
+            }
+            let (left_type, right_type) = (self.infer.describe(&left_type), self.infer.describe(&right_type));
+            return self.error(format!("Cannot {} {} and {}", action, left_type, right_type), span);
+        }
+        if self.coerce_literal(left, &right_type) { return right_type; }
+        if self.coerce_literal(right, &left_type) { return left_type; }
+        if (self.infer.is_open(&left_type) || self.infer.is_open(&right_type)) && self.infer.unify(&left_type, &right_type, span) {
+            return left_type;
+        }
+        let (left_type, right_type) = (self.infer.resolve(&left_type), self.infer.resolve(&right_type));
+        left_type.promote(&right_type).unwrap_or_else(|| {
+            self.error(format!("Cannot mix {} and {} without an explicit conversion", left_type, right_type), span)
+        })
+    }
+
+    /// Finds the type two already checked values have in common: numbers are
+    /// promoted, other values must have the same type.
+    fn unify_operands(&mut self, left: &mut Expression, right: &mut Expression, left_type: Type, right_type: Type, span: &Span, action: &str) -> Type {
+        let is_number = |expr: &Expression, ty: &Type| {
+            matches!(expr.kind, ExpressionKind::IntegerLiteral(_) | ExpressionKind::FloatLiteral(_)) || matches!(ty, Type::Int(_) | Type::Float(_))
+        };
+        // A value mixed with nil, or with optional values, becomes optional.
+        if self.try_wrap(left, &left_type, &right_type) { return right_type; }
+        if self.try_wrap(right, &right_type, &left_type) { return left_type; }
+        if is_number(left, &self.infer.shallow(&left_type)) || is_number(right, &self.infer.shallow(&right_type)) {
+            return self.numeric_operands(left, right, left_type, right_type, span, action);
+        }
+        if !self.infer.unify(&left_type, &right_type, span) {
+            let (left_type, right_type) = (self.infer.describe(&left_type), self.infer.describe(&right_type));
+            return self.error(format!("Cannot {} {} and {}", action, left_type, right_type), span);
+        }
+        left_type
+    }
+
+    /// Checks a comparison. Numbers of different types are compared after
+    /// promotion; other values only compare to values of the same type, and
+    /// only numbers and strings are ordered. Values of optional type may only
+    /// be compared with `nil`.
+    fn check_comparison(&mut self, op: &Token, left: &mut Expression, right: &mut Expression, span: &Span) {
+        let left_type = self.check_expression(left);
+        let right_type = self.check_expression(right);
+        if left.kind == ExpressionKind::Nil || right.kind == ExpressionKind::Nil {
+            let (value, ty, nil_type) = if right.kind == ExpressionKind::Nil { (&*left, &left_type, &right_type) } else { (&*right, &right_type, &left_type) };
+            if !matches!(op, Token::Eq | Token::NotEq) {
+                self.error("Cannot order nil", span);
+            } else if let ExpressionKind::Unwrap(variable) = &value.kind {
+                let ExpressionKind::Identifier(name) = &variable.kind else { unreachable!("only variables are narrowed") };
+                self.warning(format!("'{}' cannot be nil here", name), span);
+            } else if !self.infer.unify(ty, nil_type, span) {
+                let ty = self.infer.describe(ty);
+                self.error(format!("Cannot compare a value of type {} with nil; only optional types such as {}? can be nil", ty, ty), span);
+            }
+            return;
+        }
+        if let Some(optional) = [&left_type, &right_type].into_iter().find(|ty| matches!(self.infer.shallow(ty), Type::Optional(_))).cloned() {
+            self.possibly_nil("compare", &optional, span);
+            return;
+        }
+        let ty = self.unify_operands(left, right, left_type, right_type, span, "compare");
+        // Enum values are references, so `==` would not compare their contents.
+        match self.infer.resolve(&ty) {
+            Type::Enum(name) => { self.error(format!("Cannot compare values of enum type {}; use 'match'", name), span); return; }
+            ty @ Type::Result { .. } => { self.error(format!("Cannot compare values of type {}; use 'match'", ty), span); return; }
+            Type::Function { .. } => { self.error("Cannot compare functions", span); return; }
+            Type::Tuple(_) => { self.error("Cannot compare several values at once", span); return; }
+            ty @ (Type::Dyn(_) | Type::Param { bound: Some(Bound::Interface(_)), .. }) => { self.error(format!("Cannot compare values of type {}", ty), span); return; }
+            Type::Param { name, bound: None } => { self.error(format!("Cannot compare values of type {}; give it a bound, as in <{}: Ordered>", name, name), span); return; }
+            _ => {}
+        }
+        let ordered = !matches!(op, Token::Eq | Token::NotEq);
+        if ordered && !matches!(self.infer.resolve(&ty), Type::String | Type::Int(_) | Type::Float(_) | Type::Param { bound: Some(Bound::Number | Bound::Ordered), .. } | Type::Error) && !(self.infer.is_open(&ty) && self.infer.require_ordered(&ty, span)) {
+            self.error(format!("Cannot order values of type {}", self.infer.describe(&ty)), span);
+        }
+    }
+
+    /// The element type of the array or slice `target`, which is being indexed
+    /// or sliced. A value of undetermined type is taken to be a slice.
+    fn element_type(&mut self, target: &mut Expression) -> Type {
+        let ty = self.check_expression(target);
+        if self.infer.is_open(&ty) {
+            let elem = self.infer.fresh();
+            self.infer.unify(&ty, &Type::Slice(Box::new(elem.clone())), &target.span);
+            return elem;
+        }
+        match self.infer.resolve(&ty) {
+            Type::Error => Type::Error,
+            ty @ Type::Optional(_) => self.possibly_nil("index", &ty, &target.span),
+            ty => match ty.element() {
+                Some(elem) => elem.clone(),
+                None => self.error(format!("Cannot index a value of type {}", ty), &target.span),
+            },
+        }
+    }
+
+    /// Requires `ty` to be an integer type; an undetermined type becomes `i64`.
+    fn require_integer(&mut self, ty: &Type, span: &Span, what: &str) -> Type {
+        if self.infer.is_open(ty) { self.infer.unify(ty, &Type::I64, span); }
+        match self.infer.resolve(ty) {
+            ty @ (Type::Int(_) | Type::Error) => ty,
+            ty => self.error(format!("{} must be an integer, got {}", what, ty), span),
+        }
+    }
+
+    fn check_statement(&mut self, stmt: &mut Statement) {
+        if matches!(stmt.kind, StatementKind::While { .. } | StatementKind::NumericFor { .. } | StatementKind::ForIn { .. } | StatementKind::Repeat { .. }) {
+            self.forget_assigned(stmt);
+        }
+        let span = &stmt.span;
+        match &mut stmt.kind {
+            StatementKind::Let { vars, values, is_const } => {
+                let kind = if *is_const { SymbolKind::Const } else { SymbolKind::Local };
+                match (&mut vars[..], &mut values[..]) {
+                    ([var], [value]) => {
+                        var.ty = self.check_initializer(var, value);
+                        self.declare(&var.name, var.ty.clone(), &var.span, kind);
+                    }
+                    (vars, [value]) => self.check_destructuring(vars, value, kind),
+                    (vars, values) => self.check_initializers(vars, values, kind, span),
+                }
+            }
+            StatementKind::Assign { target, op, value } => {
+                let (mut target_type, name) = match &target.kind {
+                    ExpressionKind::Identifier(name) => {
+                        // Assigning to a variable does not count as using it.
+                        let (target_type, kind) = match self.resolve(name, &target.span) {
+                            Ok(sym) => (sym.ty.clone(), sym.kind),
+                            Err(err) => { self.report(err); (Type::Error, SymbolKind::Local) }
+                        };
+                        // The closure may run while the variable is narrowed.
+                        let depth = self.scopes.iter().rposition(|scope| scope.symbols.contains_key(name));
+                        if self.closures.last().is_some_and(|closure| depth.is_some_and(|depth| depth < closure.depth)) {
+                            self.mutated_captures.insert(name.clone());
+                            self.forget(name);
+                        }
+                        if !kind.is_assignable() {
+                            let what = match kind {
+                                SymbolKind::Const => "constant", SymbolKind::LoopVar => "loop variable", SymbolKind::Binding => "match binding",
+                                SymbolKind::Variant => "enum variant", _ => "function",
+                            };
+                            self.error(format!("Cannot assign to {} '{}'", what, name), &target.span);
+                        }
+                        (target_type, format!("'{}'", name))
+                    }
+                    // The elements of constant arrays may change, as in Lua.
+                    ExpressionKind::Index { .. } => (self.check_expression(target), "array element".to_string()),
+                    ExpressionKind::Field { name, .. } => {
+                        let what = format!("field '{}'", name);
+                        (self.check_expression(target), what)
+                    }
+                    _ => {
+                        self.check_expression(value);
+                        self.error("Invalid assignment target", &target.span);
+                        return;
+                    }
+                };
+                // Updating a narrowed variable keeps it from being nil, while
+                // assigning to it ends the narrowing once the value is known.
+                let variable = match &target.kind { ExpressionKind::Identifier(name) => Some(name.clone()), _ => None };
+                let narrowed = variable.as_deref().and_then(|name| self.narrowed(name)).filter(|_| op.is_some());
+                if let Some(narrowed) = narrowed {
+                    let variable = Expression { ty: mem::replace(&mut target_type, narrowed), ..(**target).clone() };
+                    target.kind = ExpressionKind::Unwrap(Box::new(variable));
+                }
+                if op.is_some() {
+                    let val_type = self.check_expression(value);
+                    let ok = if self.coerce_literal(value, &target_type) { true }
+                        else if self.infer.is_open(&target_type) || self.infer.is_open(&val_type) {
+                            self.infer.require_numeric(&target_type, span) && self.infer.require_numeric(&val_type, span) && self.infer.unify(&target_type, &val_type, &value.span)
+                        } else {
+                            let (target_type, val_type) = (self.infer.resolve(&target_type), self.infer.resolve(&val_type));
+                            target_type.promote(&val_type).is_some_and(|ty| ty.converts_to(&target_type))
+                        };
+                    if !ok {
+                        let (target_type, val_type) = (self.infer.describe(&target_type), self.infer.describe(&val_type));
+                        self.error(format!("Cannot update {} of type {} with a value of type {}", name, target_type, val_type), span);
+                    }
+                } else {
+                    self.expect_assignable(value, &target_type, &format!("Cannot assign to {}", name));
+                    if let Some(variable) = variable { self.forget(&variable); }
+                }
+                target.ty = target_type;
+            }
+            StatementKind::Return(values) => match self.return_type.clone() {
+                Some(ret) => {
+                    self.check_return(values, &ret, span);
+                    self.returned = true;
+                }
+                None => for value in values.iter_mut() { self.check_expression(value); },
+            },
+            StatementKind::Expression(expr) => match &mut expr.kind {
+                // An `if` statement: its branches need not produce values.
+                ExpressionKind::If { condition, consequence, alternative } => {
+                    self.expect_type(condition, Type::Bool, "If condition");
+                    self.check_narrowed_block(consequence, self.narrowings(condition, true));
+                    if let Some(alt) = alternative { self.check_narrowed_block(alt, self.narrowings(condition, false)); }
+                    expr.ty = Type::Void;
+                }
+                // Likewise a `match` statement.
+                ExpressionKind::Match { subject, arms } => { expr.ty = self.check_match(subject, arms, &expr.span, false); }
+                _ => { self.check_expression(expr); }
             },
-            Expression::Call { function, arguments } => {
-                 if let Expression::Identifier(name) = &**function {
-                    let func_type = self.symbol_table.get(name).ok_or_else(|| format!("Undeclared function: {}", name))?;
-                    if *func_type != Type::Function { return Err(format!("'{}' is not a function", name)); }
-                    for arg in arguments { self.check_expression(arg)?; }
-                    Ok(Type::Unknown)
-                } else { Err("Can only call named functions".to_string()) }
+            StatementKind::Function { name, receiver, params, return_annotation, body, ret, .. } => {
+                self.type_params = if receiver.is_none() { self.type_params_of(name) } else { HashMap::new() };
+                let name = match receiver { Some(receiver) => format!("{}:{}", receiver, name), None => name.clone() };
+                self.check_function(&name, params, return_annotation.is_some(), body, ret, span);
+                self.type_params.clear();
+            }
+            StatementKind::While { condition, body } => {
+                self.expect_type(condition, Type::Bool, "While condition");
+                self.check_loop_body(LoopKind::While, body, self.narrowings(condition, true));
+            }
+            StatementKind::NumericFor { var, start, stop, step, body } => {
+                let var_type = self.check_for_bounds(start, stop, step.as_deref_mut());
+                // The control variable lives in its own scope around the body.
+                self.push_scope(ScopeKind::Block, &Vec::new());
+                self.declare(var, var_type, span, SymbolKind::LoopVar);
+                self.check_loop_body(LoopKind::For, body, Vec::new());
+                self.pop_scope();
+            }
+            StatementKind::ForIn { index, value, iterable, body } => {
+                // Arrays are the only collections, iterated with `ipairs`.
+                let elem = match &mut iterable.kind {
+                    ExpressionKind::Call { function, arguments } if matches!(&function.kind, ExpressionKind::Identifier(name) if name == "ipairs") && arguments.len() == 1 => {
+                        self.element_type(&mut arguments[0])
+                    }
+                    _ => self.error("Only 'ipairs(array)' can be iterated over with 'for ... in'", &iterable.span),
+                };
+                self.push_scope(ScopeKind::Block, &Vec::new());
+                self.declare(index, Type::I64, span, SymbolKind::LoopVar);
+                if let Some(value) = value { self.declare(value, elem, span, SymbolKind::LoopVar); }
+                self.check_loop_body(LoopKind::For, body, Vec::new());
+                self.pop_scope();
+            }
+            StatementKind::Repeat { body, condition } => {
+                // As in Lua, the `until` condition can see the body's locals.
+                self.push_scope(ScopeKind::Block, body);
+                self.loops.push(LoopKind::Repeat);
+                self.check_statements(body);
+                self.loops.pop();
+                self.expect_type(condition, Type::Bool, "Until condition");
+                self.pop_scope();
+            }
+            StatementKind::Record { name, .. } => self.check_type_declaration("Record", name, span),
+            StatementKind::Enum { name, .. } => self.check_type_declaration("Enum", name, span),
+            StatementKind::Interface { name, .. } => self.check_type_declaration("Interface", name, span),
+            StatementKind::Break => {
+                if self.loops.is_empty() { self.error("'break' outside of a loop", span); }
+            }
+            StatementKind::Continue => match self.loops.last() {
+                None => { self.error("'continue' outside of a loop", span); }
+                // Jumping to the `until` check could skip the initialization of
+                // body locals the condition refers to.
+                Some(LoopKind::Repeat) => { self.error("'continue' cannot be used inside 'repeat ... until'", span); }
+                Some(_) => {}
             },
-            _ => Err("Unsupported expression type".to_string())
+        }
+    }
+
+    /// Types are declared by `analyze` up front, which only sees the top level.
+    fn check_type_declaration(&mut self, kind: &str, name: &str, span: &Span) {
+        if self.in_function() || self.scopes.len() > 2 { self.error(format!("{} '{}' must be declared at the top level", kind, name), span); }
+    }
+
+    /// Checks a `match`, whose arms must each produce a value if it is used as
+    /// one (`as_value`). Returns the type of that value.
+    fn check_match(&mut self, subject: &mut Expression, arms: &mut [MatchArm], span: &Span, as_value: bool) -> Type {
+        let ty = self.check_expression(subject);
+        let mut arm_types = Vec::new();
+        for arm in arms.iter_mut() {
+            // The pattern's bindings are visible in the guard and the body.
+            self.push_scope(ScopeKind::Block, &Vec::new());
+            self.check_pattern(&mut arm.pattern, &ty);
+            if let Some(guard) = &mut arm.guard { self.expect_type(guard, Type::Bool, "Match guard"); }
+            if as_value { arm_types.push(self.check_value_block(&mut arm.body, &arm.span, Vec::new())); } else { self.check_block(&mut arm.body); }
+            self.pop_scope();
+        }
+        self.check_exhaustive(&ty, arms, span);
+        if !as_value { return Type::Void; }
+        // As with array elements, numbers of different types are promoted.
+        let mut result = None;
+        for (i, ty) in arm_types.into_iter().enumerate() {
+            result = Some(match result {
+                None => ty,
+                Some(prev) => {
+                    let (before, rest) = arms.split_at_mut(i);
+                    let span = rest[0].span.clone();
+                    match (Self::block_value(&mut before[i - 1].body), Self::block_value(&mut rest[0].body)) {
+                        (Some(prev_value), Some(value)) => self.unify_operands(prev_value, value, prev, ty, &span, "mix match arms of type"),
+                        // The arm missing a value was already reported.
+                        _ => Type::Error,
+                    }
+                }
+            });
+        }
+        let result = result.unwrap_or(Type::Error);
+        for arm in arms.iter_mut() {
+            if let Some(value) = Self::block_value(&mut arm.body) { self.coerce_literal(value, &result); }
+        }
+        result
+    }
+
+    /// Checks `pattern` against a value of type `ty` and declares the names it binds.
+    fn check_pattern(&mut self, pattern: &mut Pattern, ty: &Type) {
+        pattern.ty = ty.clone();
+        // A bare variant name stands for the variant rather than a new binding,
+        // as do `ok` and `error` when matching a `Result`.
+        if let PatternKind::Binding(name) = &pattern.kind {
+            let outcome = matches!(name.as_str(), "ok" | "error") && matches!(self.infer.resolve(ty), Type::Result { .. });
+            if outcome || self.enum_of_variant(name).is_some() { pattern.kind = PatternKind::Variant { name: name.clone(), fields: Vec::new() }; }
+        }
+        match &mut pattern.kind {
+            PatternKind::Wildcard => {}
+            PatternKind::Binding(name) => self.declare(name, ty.clone(), &pattern.span, SymbolKind::Binding),
+            PatternKind::Literal(value) if matches!(self.infer.shallow(ty), Type::Optional(_)) => {
+                self.check_expression(value);
+                self.possibly_nil("match a literal against", ty, &pattern.span);
+            }
+            PatternKind::Literal(value) => self.expect_assignable(value, ty, "Pattern"),
+            PatternKind::Variant { name, fields } => {
+                // A value of undetermined type is taken to be of the variant's enum.
+                if let (true, Some(e)) = (self.infer.is_open(ty), self.enum_of_variant(name)) { self.infer.unify(ty, &Type::Enum(e), &pattern.span); }
+                if self.infer.is_open(ty) && matches!(name.as_str(), "ok" | "error") {
+                    let outcome = Type::Result { ok: Box::new(self.infer.fresh()), err: Box::new(self.infer.fresh()) };
+                    self.infer.unify(ty, &outcome, &pattern.span);
+                }
+                let field_types = match self.infer.resolve(ty) {
+                    Type::Error => None,
+                    // A successful `Result` of nothing has no value to bind.
+                    Type::Result { ok, .. } if name == "ok" => Some(if *ok == Type::Void { vec![] } else { vec![*ok] }),
+                    Type::Result { err, .. } if name == "error" => Some(vec![*err]),
+                    ty @ Type::Result { .. } => { self.error(format!("Cannot match a value of type {} against '{}'; use 'ok' or 'error'", ty, name), &pattern.span); None }
+                    Type::Enum(e) => match self.enums[&e].iter().find(|(variant, _)| variant == name) {
+                        Some((_, types)) => Some(types.clone()),
+                        None => { self.error(format!("Enum {} has no variant '{}'", e, name), &pattern.span); None }
+                    },
+                    ty => { self.error(format!("Cannot match a value of type {} against variant '{}'", self.infer.describe(&ty), name), &pattern.span); None }
+                };
+                match field_types {
+                    Some(types) if types.len() == fields.len() => for (field, ty) in fields.iter_mut().zip(&types) { self.check_pattern(field, ty); },
+                    Some(types) => {
+                        self.error(format!("Variant '{}' has {} field(s), but the pattern has {}", name, types.len(), fields.len()), &pattern.span);
+                        for field in fields.iter_mut() { self.check_pattern(field, &Type::Error); }
+                    }
+                    None => for field in fields.iter_mut() { self.check_pattern(field, &Type::Error); },
+                }
+            }
+        }
+    }
+
+    /// Reports the values of type `ty` that none of the arms of a `match`
+    /// cover, and the first arm that can never match because the arms before
+    /// it cover everything. Arms with a guard are taken to cover nothing.
+    fn check_exhaustive(&mut self, ty: &Type, arms: &[MatchArm], span: &Span) {
+        let ty = self.infer.resolve(ty);
+        if ty == Type::Error { return; }
+        let mut rows: Vec<Vec<Option<&Pattern>>> = Vec::new();
+        for arm in arms {
+            if self.uncovered(&rows, std::slice::from_ref(&ty)).is_none() {
+                self.warning("This arm can never match, since the arms before it cover every value", &arm.span);
+                break;
+            }
+            if arm.guard.is_none() { rows.push(vec![Some(&arm.pattern)]); }
+        }
+        // Each variant with values left over, named alone if no arm matches any
+        // of its values, and otherwise by a pattern for one that is left over.
+        let uncovered: Vec<(String, bool)> = match self.constructors(&ty) {
+            Some(constructors) => constructors.into_iter().filter_map(|(name, fields)| {
+                let specialized = Self::specialize(&rows, &name, fields.len());
+                let values = self.uncovered(&specialized, &fields)?;
+                Some(if specialized.is_empty() { (name, true) } else { (Self::constructed(&name, &values), false) })
+            }).collect(),
+            None => self.uncovered(&rows, std::slice::from_ref(&ty)).map(|_| (String::new(), true)).into_iter().collect(),
+        };
+        if uncovered.is_empty() { return; }
+        let quoted: Vec<_> = uncovered.iter().map(|(v, _)| format!("'{}'", v)).collect();
+        let message = match &ty {
+            _ if uncovered.iter().any(|(_, whole)| !whole) => format!("Match does not cover {} of {}", quoted.join(", "), ty),
+            Type::Enum(_) | Type::Result { .. } if quoted.len() == 1 => format!("Match does not cover variant {} of {}", quoted[0], ty),
+            Type::Enum(_) => format!("Match does not cover variants {} of {}", quoted.join(", "), ty),
+            Type::Bool => format!("Match does not cover the value {}", uncovered[0].0),
+            ty => format!("Match does not cover every value of type {}; add a '_' arm", self.infer.describe(ty)),
+        };
+        self.error(message, span);
+    }
+
+    /// Values of the types `tys` that no row of patterns in `rows` matches,
+    /// written as patterns, or `None` if the rows cover every value. `None`
+    /// in a row stands for `_`. This is the usefulness check of "Warnings for
+    /// pattern matching" (Maranget, 2007), keeping the values it finds.
+    fn uncovered(&self, rows: &[Vec<Option<&Pattern>>], tys: &[Type]) -> Option<Vec<String>> {
+        let Some((ty, rest)) = tys.split_first() else { return rows.is_empty().then(Vec::new) };
+        let catch_all = |pattern: Option<&Pattern>| pattern.is_none_or(|p| matches!(p.kind, PatternKind::Wildcard | PatternKind::Binding(_)));
+        // Patterns of an erroneous type were reported already; take them to match anything.
+        if self.infer.resolve(ty) == Type::Error { return self.uncovered(&rows.iter().map(|row| row[1..].to_vec()).collect::<Vec<_>>(), rest).map(|values| [String::from("_")].into_iter().chain(values).collect()); }
+        let constructors = self.constructors(ty).unwrap_or_default();
+        let heads: HashSet<&str> = rows.iter().filter_map(|row| Self::head(row[0])).collect();
+        // When the rows start with every constructor, the values left over are
+        // those left over by one of them.
+        if !constructors.is_empty() && constructors.iter().all(|(name, _)| heads.contains(name.as_str())) {
+            return constructors.iter().find_map(|(name, fields)| {
+                let tys: Vec<Type> = fields.iter().chain(rest).cloned().collect();
+                let mut values = self.uncovered(&Self::specialize(rows, name, fields.len()), &tys)?;
+                let rest = values.split_off(fields.len());
+                Some([Self::constructed(name, &values)].into_iter().chain(rest).collect())
+            });
+        }
+        // Otherwise some constructor, or some literal, is left to the rows
+        // starting with `_`.
+        let defaults: Vec<_> = rows.iter().filter(|row| catch_all(row[0])).map(|row| row[1..].to_vec()).collect();
+        let values = self.uncovered(&defaults, rest)?;
+        let missing = constructors.iter().find(|(name, _)| !heads.contains(name.as_str()));
+        let value = missing.map_or_else(|| "_".to_string(), |(name, fields)| Self::constructed(name, &vec!["_".to_string(); fields.len()]));
+        Some([value].into_iter().chain(values).collect())
+    }
+
+    /// The variants of `ty`, or its values `true` and `false`, with the types
+    /// of their fields. `None` for types with too many values to list.
+    fn constructors(&self, ty: &Type) -> Option<Vec<(String, Vec<Type>)>> {
+        match self.infer.resolve(ty) {
+            Type::Enum(e) => self.enums.get(&e).cloned(),
+            // A successful `Result` of nothing has no value to match.
+            Type::Result { ok, err } => Some(vec![("ok".to_string(), if *ok == Type::Void { vec![] } else { vec![*ok] }), ("error".to_string(), vec![*err])]),
+            Type::Bool => Some(vec![("true".to_string(), vec![]), ("false".to_string(), vec![])]),
+            _ => None,
+        }
+    }
+
+    /// The constructor a pattern starts with: a variant or a boolean.
+    fn head(pattern: Option<&Pattern>) -> Option<&str> {
+        match &pattern?.kind {
+            PatternKind::Variant { name, .. } => Some(name),
+            PatternKind::Literal(literal) => match literal.kind { ExpressionKind::Boolean(value) => Some(if value { "true" } else { "false" }), _ => None },
+            _ => None,
+        }
+    }
+
+    /// The rows of `rows` that match a value built by the constructor `name`
+    /// with `arity` fields, with their first pattern replaced by the patterns
+    /// for those fields.
+    fn specialize<'a>(rows: &[Vec<Option<&'a Pattern>>], name: &str, arity: usize) -> Vec<Vec<Option<&'a Pattern>>> {
+        rows.iter().filter_map(|row| {
+            let fields: Vec<Option<&Pattern>> = match row[0].map(|p| &p.kind) {
+                None | Some(PatternKind::Wildcard | PatternKind::Binding(_)) => vec![None; arity],
+                // A pattern with the wrong number of fields was reported already.
+                Some(PatternKind::Variant { name: other, fields }) if other == name => if fields.len() == arity { fields.iter().map(Some).collect() } else { vec![None; arity] },
+                _ if Self::head(row[0]) == Some(name) => Vec::new(),
+                _ => return None,
+            };
+            Some(fields.into_iter().chain(row[1..].iter().copied()).collect())
+        }).collect()
+    }
+
+    /// A pattern for a value built by the constructor `name` from `fields`.
+    fn constructed(name: &str, fields: &[String]) -> String {
+        if fields.is_empty() { name.to_string() } else { format!("{}({})", name, fields.join(", ")) }
+    }
+
+    /// Checks the bounds of a numeric `for` and returns the type of its control
+    /// variable: the common type of all bounds, with integer literals adapting.
+    fn check_for_bounds(&mut self, start: &mut Expression, stop: &mut Expression, step: Option<&mut Expression>) -> Type {
+        let mut bounds = vec![start, stop];
+        bounds.extend(step);
+        let mut var_type: Option<Type> = None;
+        for bound in bounds.iter_mut() {
+            let mut ty = self.check_expression(bound);
+            if !self.infer.require_numeric(&ty, &bound.span) { ty = self.error(format!("For loop bounds must be numbers, got {}", self.infer.describe(&ty)), &bound.span); }
+            if matches!(bound.kind, ExpressionKind::IntegerLiteral(_)) { continue; }
+            var_type = Some(match var_type {
+                None => ty,
+                Some(prev) if (self.infer.is_open(&prev) || self.infer.is_open(&ty)) && self.infer.unify(&prev, &ty, &bound.span) => prev,
+                Some(prev) => {
+                    let (prev, ty) = (self.infer.resolve(&prev), self.infer.resolve(&ty));
+                    prev.promote(&ty).unwrap_or_else(|| self.error(format!("Cannot mix {} and {} in for loop bounds", prev, ty), &bound.span))
+                }
+            });
+        }
+        let var_type = var_type.unwrap_or(Type::I64);
+        for bound in bounds { self.coerce_literal(bound, &var_type); }
+        var_type
+    }
+
+    /// Checks the initial `value` of the variable `var` of a `local` and
+    /// returns the variable's type.
+    fn check_initializer(&mut self, var: &Variable, value: &mut Expression) -> Type {
+        if matches!(value.kind, ExpressionKind::FunctionLiteral { .. }) { self.local_closures.insert(var.span.byte_range.start, value.span.byte_range.start); }
+        if let Some(declared) = self.resolve_annotation(&var.annotation) {
+            self.expect_assignable(value, &declared, &format!("Cannot initialize '{}'", var.name));
+            return declared;
+        }
+        let val_type = self.check_expression(value);
+        match self.infer.resolve(&val_type) {
+            Type::Void => self.error(format!("Cannot assign a value-less expression to '{}'", var.name), &value.span),
+            Type::Tuple(elems) => {
+                let message = format!("Cannot assign {} values to '{}'; declare a variable for each, as in 'local a, b = ...'", elems.len(), var.name);
+                self.error(message, &value.span)
+            }
+            _ => val_type,
+        }
+    }
+
+    /// Checks `local a, b = x, y`, which gives each variable the value in its
+    /// place. The variables are declared after all the values are checked.
+    fn check_initializers(&mut self, vars: &mut [Variable], values: &mut [Expression], kind: SymbolKind, span: &Span) {
+        if vars.len() != values.len() { self.error(format!("Cannot assign {} values to {} variables", values.len(), vars.len()), span); }
+        let mut types = Vec::new();
+        for (i, value) in values.iter_mut().enumerate() {
+            match vars.get(i) {
+                Some(var) => types.push(self.check_initializer(var, value)),
+                None => { self.check_expression(value); }
+            }
+        }
+        for (i, var) in vars.iter_mut().enumerate() {
+            var.ty = types.get(i).cloned().unwrap_or(Type::Error);
+            if var.name != "_" { self.declare(&var.name, var.ty.clone(), &var.span, kind); }
+        }
+    }
+
+    /// Checks `local a, b = value`, which declares a variable for each of the
+    /// values of a function returning several, except those named `_`.
+    fn check_destructuring(&mut self, vars: &mut [Variable], value: &mut Expression, kind: SymbolKind) {
+        let ty = self.check_expression(value);
+        let count = vars.len();
+        let taken = Type::Tuple((0..count).map(|_| self.infer.fresh()).collect());
+        let elems = match self.infer.shallow(&ty) {
+            Type::Tuple(elems) if elems.len() == count => elems,
+            Type::Tuple(elems) => {
+                self.error(format!("Cannot unpack {} values into {} variables", elems.len(), count), &value.span);
+                vec![Type::Error; count]
+            }
+            // A function whose return type is still open returns as many values as are taken.
+            open @ Type::Var(_) if self.infer.unify(&open, &taken, &value.span) => {
+                let Type::Tuple(elems) = taken else { unreachable!("made as values above") };
+                elems
+            }
+            Type::Error => vec![Type::Error; count],
+            ty => {
+                self.error(format!("Cannot unpack a single value of type {} into {} variables", self.infer.describe(&ty), count), &value.span);
+                vec![Type::Error; count]
+            }
+        };
+        for (var, elem) in vars.iter_mut().zip(elems) {
+            var.ty = match self.resolve_annotation(&var.annotation) {
+                Some(declared) => {
+                    if !self.infer.unify(&declared, &elem, &var.span) {
+                        self.error(format!("Cannot initialize '{}': expected {}, found {}", var.name, declared, self.infer.describe(&elem)), &var.span);
+                    }
+                    declared
+                }
+                None => elem,
+            };
+            if var.name != "_" { self.declare(&var.name, var.ty.clone(), &var.span, kind); }
+        }
+    }
+
+    /// Checks the values of a `return` against the return type `ret` of the
+    /// enclosing function, which is several values when more than one is given.
+    fn check_return(&mut self, values: &mut [Expression], ret: &Type, span: &Span) {
+        if values.is_empty() && self.infer.unify(ret, &Type::Void, span) { return; }
+        if let [value] = values {
+            self.expect_assignable(value, ret, "Return value");
+            return;
+        }
+        let ret = match self.infer.shallow(ret) {
+            open @ Type::Var(_) => {
+                let elems = Type::Tuple(values.iter().map(|_| self.infer.fresh()).collect());
+                if self.infer.unify(&open, &elems, span) { elems } else { open }
+            }
+            ret => ret,
+        };
+        match ret {
+            Type::Tuple(elems) if elems.len() == values.len() => {
+                for (value, elem) in values.iter_mut().zip(&elems) { self.expect_assignable(value, elem, "Return value"); }
+            }
+            ret => {
+                for value in values.iter_mut() { self.check_expression(value); }
+                let expected = match self.infer.resolve(&ret) { Type::Tuple(elems) => elems.len(), Type::Void => 0, _ => 1 };
+                if ret != Type::Error { self.error(format!("Expected {} return value(s), got {}", expected, values.len()), span); }
+            }
+        }
+    }
+
+    /// Checks a function whose signature `analyze` set up front. Parameter and
+    /// return types that were not annotated are inference variables, which the
+    /// body and the function's callers determine.
+    fn check_function(&mut self, name: &str, params: &[Param], annotated: bool, body: &mut BlockStatement, ret: &Type, span: &Span) {
+        if self.in_function() || self.scopes.len() > 2 {
+            self.error(format!("Function '{}' must be declared at the top level", name), span);
+            return;
+        }
+        let mut assigned = HashSet::new();
+        Self::collect_assigned(body, &mut assigned);
+        let module_assigned = mem::replace(&mut self.assigned, assigned);
+        self.check_function_body(&format!("Function '{}'", name), params, annotated, body, ret, span);
+        self.assigned = module_assigned;
+    }
+
+    /// Checks the body of a function or function literal (`what`, as in
+    /// "Function 'f' never returns a value").
+    fn check_function_body(&mut self, what: &str, params: &[Param], annotated: bool, body: &mut BlockStatement, ret: &Type, span: &Span) {
+        // Parameters and the body's own locals share one scope, as they do in C.
+        self.push_scope(ScopeKind::Function, body);
+        let (return_type, returned) = (self.return_type.replace(ret.clone()), mem::take(&mut self.returned));
+        let loops = mem::take(&mut self.loops);
+        for param in params { self.declare(&param.name, param.ty.clone(), &param.span, SymbolKind::Param); }
+        self.check_statements(body);
+        self.loops = loops;
+        self.return_type = return_type;
+        self.pop_scope();
+        // Without a `return`, the function produces no value.
+        if !mem::replace(&mut self.returned, returned) && !self.infer.unify(ret, &Type::Void, span) {
+            let message = if annotated {
+                format!("{} is declared to return {} but never returns a value", what, self.infer.resolve(ret))
+            } else {
+                format!("{} never returns a value, but its result is used{}", what, self.inferred_note(ret, span))
+            };
+            self.error(message, span);
+        }
+    }
+
+    /// Checks `expr` and records its type in `expr.ty` for the backend. An
+    /// expression that fails to check is reported and gets `Type::Error`.
+    fn check_expression(&mut self, expr: &mut Expression) -> Type {
+        let ty = self.infer_expression(expr);
+        expr.ty = ty.clone();
+        ty
+    }
+
+    fn infer_expression(&mut self, expr: &mut Expression) -> Type {
+        let span = &expr.span;
+        match &mut expr.kind {
+            ExpressionKind::IntegerLiteral(_) => Type::I64,
+            ExpressionKind::FloatLiteral(_) => Type::F64,
+            ExpressionKind::StringLiteral(_) => Type::String,
+            ExpressionKind::Boolean(_) => Type::Bool,
+            // Its type is settled by where it is used.
+            ExpressionKind::Nil => Type::Optional(Box::new(self.infer.fresh())),
+            ExpressionKind::Identifier(name) => {
+                let ty = self.lookup(name, span).unwrap_or_else(|err| self.report(err));
+                // The backend implements builtins by name, at their calls.
+                if self.symbol(name).is_some_and(|sym| sym.kind == SymbolKind::Builtin) { return self.error(format!("Builtin '{}' can only be called", name), span); }
+                if self.is_generic_function(name) {
+                    let (instance, ty) = self.instantiate(&name.clone(), &ty, span);
+                    expr.kind = instance;
+                    return ty;
+                }
+                // Used as a value, a local's closure may be copied anywhere.
+                if let Some(decl) = self.symbol(name).map(|sym| sym.span.byte_range.start) { self.local_closures.remove(&decl); }
+                let Some(narrowed) = self.narrowed(name) else { return ty };
+                let variable = Expression { kind: ExpressionKind::Identifier(name.clone()), span: span.clone(), ty };
+                expr.kind = ExpressionKind::Unwrap(Box::new(variable));
+                narrowed
+            }
+            ExpressionKind::Prefix { op: Token::Hash, right } => {
+                let ty = self.check_expression(right);
+                if let ty @ Type::Optional(_) = self.infer.resolve(&ty) { return self.possibly_nil("take the length of", &ty, &right.span); }
+                if self.infer.resolve(&ty).element().is_none() { self.expect_checked_type(right, &ty, Type::String, "Operand of '#'"); }
+                Type::I64
+            },
+            ExpressionKind::Prefix { op: Token::Not, right } => {
+                self.expect_type(right, Type::Bool, "Operand of 'not'");
+                Type::Bool
+            },
+            ExpressionKind::Prefix { op: Token::Minus, right } => {
+                let ty = self.check_expression(right);
+                if !self.infer.require_numeric(&ty, span) { return self.error(format!("Cannot negate a value of type {}", self.infer.describe(&ty)), span); }
+                if let Type::Int(int) = self.infer.resolve(&ty) {
+                    if !int.is_signed() { return self.error(format!("Cannot negate a value of unsigned type {}", int), span); }
+                }
+                ty
+            },
+            ExpressionKind::Prefix { op: Token::Tilde, right } => {
+                let ty = self.check_expression(right);
+                self.require_integer(&ty, span, "Operand of '~'")
+            },
+            ExpressionKind::Infix { op: op @ (Token::And | Token::Or), left, right } => {
+                let what = if *op == Token::And { "Operand of 'and'" } else { "Operand of 'or'" };
+                let left_type = self.check_expression(left);
+                // `x or default` is the value of `x` unless that is nil. The
+                // default may be optional too, making the result optional.
+                if let (Token::Or, Type::Optional(inner)) = (&op, self.infer.shallow(&left_type)) {
+                    let right_type = self.check_expression(right);
+                    let ty = if matches!(self.infer.shallow(&right_type), Type::Optional(_)) { left_type } else { *inner };
+                    self.expect_checked_assignable(right, &right_type, &ty, "Default value of 'or'");
+                    return ty;
+                }
+                self.expect_checked_type(left, &left_type, Type::Bool, what);
+                // The right operand is only evaluated when the left one is true
+                // for `and`, or false for `or`.
+                self.push_scope(ScopeKind::Block, &Vec::new());
+                self.narrow(self.narrowings(left, *op == Token::And));
+                self.expect_type(right, Type::Bool, what);
+                self.pop_scope();
+                Type::Bool
+            },
+            ExpressionKind::Infix { op: Token::Concat, left, right } => {
+                // As in Lua, numbers are converted to their decimal representation.
+                for operand in [left, right] {
+                    let ty = self.check_expression(operand);
+                    if self.infer.is_open(&ty) {
+                        // Operands known to be numbers stay numbers; any other is taken to be a string.
+                        self.infer.unify(&ty, &Type::String, &operand.span);
+                        continue;
+                    }
+                    let ty = self.infer.resolve(&ty);
+                    if !ty.compatible(&Type::String) && !ty.is_numeric() {
+                        self.error(format!("Cannot concatenate a value of type {}", ty), &operand.span);
+                    }
+                }
+                Type::String
+            },
+            ExpressionKind::Infix { op: op @ (Token::Eq | Token::NotEq | Token::Lt | Token::Gt | Token::LtEq | Token::GtEq), left, right } => {
+                self.check_comparison(op, left, right, span);
+                Type::Bool
+            },
+            ExpressionKind::Infix { op: Token::Caret, left, right } => {
+                // As in Lua, exponentiation always produces a float.
+                self.check_arithmetic(left, right, span);
+                Type::F64
+            },
+            ExpressionKind::Infix { op: Token::Ampersand | Token::Pipe | Token::Tilde, left, right } => {
+                let ty = self.check_arithmetic(left, right, span);
+                self.require_integer(&ty, span, "Bitwise operand")
+            },
+            ExpressionKind::Infix { op: Token::ShiftLeft | Token::ShiftRight, left, right } => {
+                // The result has the type of the shifted value; the shift count
+                // may be any integer.
+                let ty = self.check_expression(left);
+                let ty = self.require_integer(&ty, &left.span, "Shifted value");
+                let count = self.check_expression(right);
+                self.require_integer(&count, &right.span, "Shift count");
+                ty
+            },
+            ExpressionKind::Infix { op:_, left, right } => self.check_arithmetic(left, right, span),
+            ExpressionKind::If { condition, consequence, alternative } => {
+                self.expect_type(condition, Type::Bool, "If condition");
+                let Some(alternative) = alternative else {
+                    self.check_block(consequence);
+                    return self.error("An 'if' used as a value needs an 'else' branch", span);
+                };
+                let then_type = self.check_value_block(consequence, span, self.narrowings(condition, true));
+                let else_type = self.check_value_block(alternative, span, self.narrowings(condition, false));
+                match (Self::block_value(consequence), Self::block_value(alternative)) {
+                    (Some(then_value), Some(else_value)) => self.unify_operands(then_value, else_value, then_type, else_type, span, "mix 'if' branches of type"),
+                    // The branch missing a value was already reported.
+                    _ => Type::Error,
+                }
+            },
+            ExpressionKind::Call { function, arguments } => {
+                let (name, named) = (Self::callee_name(function), matches!(function.kind, ExpressionKind::Identifier(_)));
+                let func_type = if let ExpressionKind::Identifier(name) = &function.kind {
+                    // Naming a function to call it is not using it as a value.
+                    let ty = self.lookup(name, &function.span).unwrap_or_else(|err| self.report(err));
+                    let ty = if self.is_generic_function(name) {
+                        let (instance, ty) = self.instantiate(&name.clone(), &ty, &function.span);
+                        function.kind = instance;
+                        ty
+                    } else { ty };
+                    function.ty = self.infer.shallow(&ty);
+                    function.ty.clone()
+                } else {
+                    let ty = self.check_expression(function);
+                    self.infer.shallow(&ty)
+                };
+                match name.as_str() {
+                    "error" if named => return self.check_error_call(arguments, span),
+                    "pcall" if named => return self.check_pcall(arguments, span),
+                    _ => {}
+                }
+                let (params, ret) = match func_type {
+                    Type::Function { params, ret } => (params, *ret),
+                    // A value of undetermined type is taken to be a function of the arguments.
+                    ty @ Type::Var(_) => {
+                        let (params, ret): (Vec<_>, _) = (arguments.iter().map(|_| self.infer.fresh()).collect(), self.infer.fresh());
+                        self.infer.unify(&ty, &Type::Function { params: params.clone(), ret: Box::new(ret.clone()) }, &function.span);
+                        (params, ret)
+                    }
+                    ty => {
+                        for arg in arguments.iter_mut() { self.check_expression(arg); }
+                        return match ty {
+                            Type::Error => ty,
+                            _ if named => self.error(format!("'{}' is not a function", name), &function.span),
+                            ty => self.error(format!("Cannot call a value of type {}", self.infer.resolve(&ty)), &function.span),
+                        };
+                    }
+                };
+                let reported = self.diagnostics.len();
+                if !self.check_arguments("Function", &name, &params, arguments, span) { return ret; }
+                if !matches!(function.kind, ExpressionKind::Instance { .. }) { self.explain_inferred_params(&name, &params, arguments, reported); }
+                if name == "print" && matches!(self.infer.shallow(&arguments[0].ty), Type::Optional(_)) {
+                    self.possibly_nil("print", &arguments[0].ty.clone(), &arguments[0].span);
+                } else if name == "print" && matches!(self.infer.resolve(&arguments[0].ty), Type::Array { .. } | Type::Slice(_) | Type::Record(_) | Type::Enum(_) | Type::Result { .. } | Type::Function { .. } | Type::Tuple(_) | Type::Generic { .. } | Type::Dyn(_) | Type::Param { bound: None | Some(Bound::Interface(_)), .. }) {
+                    self.error(format!("Cannot print a value of type {}", self.infer.resolve(&arguments[0].ty)), &arguments[0].span);
+                }
+                if matches!(Type::from_name(&name), Some(Type::Int(_) | Type::Float(_))) && !self.infer.require_numeric(&arguments[0].ty, &arguments[0].span) {
+                    self.error(format!("Cannot convert {} to {}", self.infer.describe(&arguments[0].ty), name), &arguments[0].span);
+                }
+                ret
+            },
+            ExpressionKind::MethodCall { receiver, method, arguments } => {
+                let ty = self.check_expression(receiver);
+                let ty = self.infer.resolve(&ty);
+                let sig = match &ty {
+                    Type::Error => None,
+                    Type::Var(_) => Some(self.error(format!("The type of this value must be known to call its method '{}'; add a type annotation", method), &receiver.span)),
+                    Type::Optional(_) => Some(self.possibly_nil(&format!("call method '{}' of", method), &ty, &receiver.span)),
+                    ty => Some(self.method_signature(ty, method).unwrap_or_else(|| self.error(format!("Type {} has no method '{}'", ty, method), span))),
+                };
+                let Some(Type::Function { params, ret }) = sig else {
+                    for arg in arguments.iter_mut() { self.check_expression(arg); }
+                    return Type::Error;
+                };
+                self.check_arguments("Method", &format!("{}:{}", ty, method), &params, arguments, span);
+                *ret
+            },
+            ExpressionKind::ArrayLiteral(elements) => {
+                // Elements of different number types are promoted to a common type.
+                let mut elem = self.infer.fresh();
+                for i in 0..elements.len() {
+                    let ty = self.check_expression(&mut elements[i]);
+                    elem = if i == 0 { ty } else {
+                        let (before, rest) = elements.split_at_mut(i);
+                        let span = rest[0].span.clone();
+                        self.unify_operands(&mut before[i - 1], &mut rest[0], elem, ty, &span, "mix array elements of type")
+                    };
+                }
+                for element in elements.iter_mut() { self.coerce_literal(element, &elem); }
+                Type::Array { elem: Box::new(elem), size: None }
+            },
+            ExpressionKind::Index { target, index } => {
+                let elem = self.element_type(target);
+                let ty = self.check_expression(index);
+                self.require_integer(&ty, &index.span, "Array index");
+                if let (ExpressionKind::IntegerLiteral(i), Type::Array { size: Some(size), .. }) = (&index.kind, self.infer.resolve(&target.ty)) {
+                    if !(1..=size as i128).contains(i) { self.error(format!("Index {} is out of bounds for an array of {} elements", i, size), &index.span); }
+                }
+                elem
+            },
+            ExpressionKind::Slice { target, start, stop } => {
+                let elem = self.element_type(target);
+                for bound in [start, stop] {
+                    let ty = self.check_expression(bound);
+                    self.require_integer(&ty, &bound.span, "Slice bound");
+                }
+                Type::Slice(Box::new(elem))
+            },
+            ExpressionKind::RecordLiteral { name, fields } => {
+                let Some(declared) = self.records.get(name).cloned() else {
+                    for field in fields.iter_mut() { self.check_expression(&mut field.value); }
+                    return self.error(format!("Unknown record type '{}'", name), span);
+                };
+                // The type arguments of a generic record follow from its fields.
+                let (ty, declared) = match self.generics.get(name) {
+                    Some(_) => {
+                        let (type_args, args) = self.fresh_type_args(name, span);
+                        let declared = declared.iter().map(|(field, ty)| (field.clone(), ty.substitute(&args))).collect();
+                        (Type::Generic { name: name.clone(), args: type_args }, declared)
+                    }
+                    None => (Type::Record(name.clone()), declared),
+                };
+                let mut given = HashSet::new();
+                for field in fields.iter_mut() {
+                    match declared.iter().find(|(declared, _)| *declared == field.name) {
+                        _ if !given.insert(field.name.clone()) => {
+                            self.check_expression(&mut field.value);
+                            self.error(format!("Field '{}' is given more than once", field.name), &field.span);
+                        }
+                        Some((_, ty)) => self.expect_assignable(&mut field.value, ty, &format!("Field '{}' of '{}'", field.name, name)),
+                        None => {
+                            self.check_expression(&mut field.value);
+                            self.error(format!("Record '{}' has no field '{}'", name, field.name), &field.span);
+                        }
+                    }
+                }
+                let missing: Vec<_> = declared.iter().filter(|(field, _)| !given.contains(field)).map(|(field, _)| format!("'{}'", field)).collect();
+                if !missing.is_empty() {
+                    let plural = if missing.len() == 1 { "" } else { "s" };
+                    self.error(format!("Missing field{} {} in '{}' constructor", plural, missing.join(", "), name), span);
+                }
+                ty
+            },
+            ExpressionKind::Match { subject, arms } => self.check_match(subject, arms, span, true),
+            ExpressionKind::FunctionLiteral { params, return_annotation, body, captures, .. } => {
+                for param in params.iter_mut() {
+                    param.ty = self.resolve_annotation(&param.annotation).unwrap_or_else(|| self.infer.fresh());
+                }
+                let ret = self.resolve_return_annotation(return_annotation).unwrap_or_else(|| self.infer.fresh());
+                self.closures.push(Closure { depth: self.scopes.len(), captures: Vec::new() });
+                self.check_function_body("Anonymous function", params, return_annotation.is_some(), body, &ret, span);
+                let closure = self.closures.pop().expect("the closure was just pushed");
+                let by_reference = closure.captures.iter().filter(|(capture, _)| capture.by_reference).map(|(_, decl)| *decl).collect();
+                self.literals.push((span.byte_range.start, by_reference));
+                *captures = closure.captures.into_iter().map(|(capture, _)| capture).collect();
+                Type::Function { params: params.iter().map(|param| param.ty.clone()).collect(), ret: Box::new(ret) }
+            },
+            ExpressionKind::Try(value) => {
+                let ty = self.check_expression(value);
+                let (ok, err) = (self.infer.fresh(), self.infer.fresh());
+                if !self.infer.unify(&ty, &Type::Result { ok: Box::new(ok.clone()), err: Box::new(err.clone()) }, &value.span) {
+                    return self.error(format!("Cannot apply '?' to a value of type {}; only a Result can pass on its error", self.infer.describe(&ty)), span);
+                }
+                let Some(ret) = self.return_type.clone() else { return self.error("'?' can only be used inside a function", span) };
+                // The error is returned as it is, so the function must return
+                // a `Result` with the same error type.
+                let failure = Type::Result { ok: Box::new(self.infer.fresh()), err: Box::new(err.clone()) };
+                if !self.infer.unify(&ret, &failure, span) {
+                    let (err, ret) = (self.infer.describe(&err), self.infer.describe(&ret));
+                    return self.error(format!("'?' passes on an error of type {}, but the function returns {}", err, ret), span);
+                }
+                ok
+            },
+            ExpressionKind::Field { target, name } => {
+                let ty = self.check_expression(target);
+                match self.infer.resolve(&ty) {
+                    Type::Error => Type::Error,
+                    Type::Record(record) => self.field_type(&record, name)
+                        .unwrap_or_else(|| self.error(format!("Record '{}' has no field '{}'", record, name), span)),
+                    Type::Generic { name: record, args } => match self.field_type(&record, name) {
+                        Some(field) => {
+                            let params = self.generics[&record].iter().map(ToString::to_string);
+                            field.substitute(&params.zip(args).collect())
+                        }
+                        None => self.error(format!("Record '{}' has no field '{}'", record, name), span),
+                    },
+                    Type::Var(_) => self.error(format!("The type of this value must be known to access its field '{}'; add a type annotation", name), &target.span),
+                    ty @ Type::Optional(_) => self.possibly_nil(&format!("access field '{}' of", name), &ty, &target.span),
+                    ty => self.error(format!("Cannot access field '{}' of a value of type {}", name, ty), span),
+                }
+            },
+            _ => self.error("Unsupported expression type", span),
+        }
+    }
+
+    /// Replaces the inference variables left in the checked program by the
+    /// types they were resolved to.
+    fn finalize_block(&mut self, block: &mut BlockStatement) {
+        for stmt in block.iter_mut() { self.finalize_statement(stmt); }
+    }
+
+    fn finalize_statement(&mut self, stmt: &mut Statement) {
+        match &mut stmt.kind {
+            StatementKind::Let { vars, values, .. } => {
+                for value in values.iter_mut() { self.finalize_expression(value); }
+                for (i, var) in vars.iter_mut().enumerate() {
+                    var.ty = self.infer.finish(&var.ty);
+                    var.shared = self.shared.contains(&var.span.byte_range.start);
+                    // The initializer called a function that turned out not to return anything.
+                    let value = values.get(i).unwrap_or(&values[0]);
+                    if var.ty == Type::Void { self.error(format!("Cannot assign a value-less expression to '{}'", var.name), &value.span); }
+                }
+            }
+            StatementKind::Assign { target, value, .. } => { self.finalize_expression(target); self.finalize_expression(value); }
+            StatementKind::Return(values) => for value in values.iter_mut() { self.finalize_expression(value); },
+            StatementKind::Expression(expr) => {
+                self.finalize_expression(expr);
+                if matches!(expr.ty, Type::Result { .. }) {
+                    self.warning("The Result of this expression is discarded; handle it with 'match' or pass on its error with '?'", &expr.span);
+                }
+            }
+            StatementKind::Function { params, body, ret, .. } => {
+                self.finalize_params(params);
+                *ret = self.infer.finish(ret);
+                self.finalize_block(body);
+            }
+            StatementKind::While { condition, body } | StatementKind::Repeat { body, condition } => {
+                self.finalize_expression(condition);
+                self.finalize_block(body);
+            }
+            StatementKind::NumericFor { start, stop, step, body, .. } => {
+                self.finalize_expression(start);
+                self.finalize_expression(stop);
+                if let Some(step) = step { self.finalize_expression(step); }
+                self.finalize_block(body);
+            }
+            StatementKind::ForIn { iterable, body, .. } => {
+                self.finalize_expression(iterable);
+                self.finalize_block(body);
+            }
+            StatementKind::Record { .. } | StatementKind::Enum { .. } | StatementKind::Interface { .. } | StatementKind::Break | StatementKind::Continue => {}
+        }
+    }
+
+    fn finalize_expression(&mut self, expr: &mut Expression) {
+        expr.ty = self.infer.finish(&expr.ty);
+        match &mut expr.kind {
+            // The literal's type may have been settled after it was checked.
+            ExpressionKind::IntegerLiteral(value) => {
+                if let Type::Int(int) = expr.ty {
+                    if !Self::literal_fits(value, int) { self.error(format!("Integer literal {} does not fit in {}", value, int), &expr.span); }
+                }
+            }
+            ExpressionKind::Prefix { right, .. } => self.finalize_expression(right),
+            ExpressionKind::Infix { left, right, .. } => { self.finalize_expression(left); self.finalize_expression(right); }
+            ExpressionKind::If { condition, consequence, alternative } => {
+                self.finalize_expression(condition);
+                self.finalize_block(consequence);
+                if let Some(alt) = alternative { self.finalize_block(alt); }
+            }
+            ExpressionKind::Call { function, arguments } => {
+                self.finalize_expression(function);
+                for arg in arguments.iter_mut() { self.finalize_expression(arg); }
+            }
+            ExpressionKind::MethodCall { receiver, arguments, .. } => {
+                self.finalize_expression(receiver);
+                for arg in arguments.iter_mut() { self.finalize_expression(arg); }
+            }
+            ExpressionKind::ArrayLiteral(elements) => for element in elements.iter_mut() { self.finalize_expression(element); },
+            ExpressionKind::Index { target, index } => { self.finalize_expression(target); self.finalize_expression(index); }
+            ExpressionKind::Slice { target, start, stop } => {
+                self.finalize_expression(target);
+                self.finalize_expression(start);
+                self.finalize_expression(stop);
+            }
+            ExpressionKind::RecordLiteral { name, fields } => {
+                for field in fields.iter_mut() { self.finalize_expression(&mut field.value); }
+                if let Type::Generic { args, .. } = &expr.ty { self.check_bounds(name, args, &expr.span); }
+            }
+            ExpressionKind::Instance { name, type_args } => {
+                for arg in type_args.iter_mut() { *arg = self.infer.finish(arg); }
+                self.check_bounds(name, type_args, &expr.span);
+            }
+            ExpressionKind::Field { target, .. } | ExpressionKind::Try(target) | ExpressionKind::Wrap(target) | ExpressionKind::Unwrap(target)
+            | ExpressionKind::Dyn(target) => self.finalize_expression(target),
+            ExpressionKind::FunctionLiteral { params, body, captures, escapes, .. } => {
+                self.finalize_params(params);
+                for capture in captures.iter_mut() { capture.ty = self.infer.finish(&capture.ty); }
+                *escapes = self.escapes(expr.span.byte_range.start);
+                self.finalize_block(body);
+            }
+            ExpressionKind::Match { subject, arms } => {
+                self.finalize_expression(subject);
+                for arm in arms.iter_mut() {
+                    self.finalize_pattern(&mut arm.pattern);
+                    if let Some(guard) = &mut arm.guard { self.finalize_expression(guard); }
+                    self.finalize_block(&mut arm.body);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finalize_params(&mut self, params: &mut [Param]) {
+        for param in params.iter_mut() {
+            param.ty = self.infer.finish(&param.ty);
+            param.shared = self.shared.contains(&param.span.byte_range.start);
+        }
+    }
+
+    fn finalize_pattern(&mut self, pattern: &mut Pattern) {
+        pattern.ty = self.infer.finish(&pattern.ty);
+        match &mut pattern.kind {
+            PatternKind::Literal(value) => self.finalize_expression(value),
+            PatternKind::Variant { fields, .. } => for field in fields.iter_mut() { self.finalize_pattern(field); },
+            PatternKind::Wildcard | PatternKind::Binding(_) => {}
         }
     }
 }
@@ -0,0 +1,16 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+/// A region of source text. `line` and `col` are 1-based and point at the first
+/// character of the region; `byte_range` indexes into the original source string.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: Rc<str>, pub line: usize, pub col: usize, pub byte_range: Range<usize>,
+}
+
+impl Span {
+    /// Returns a span starting where `self` starts and ending where `other` ends.
+    pub fn join(&self, other: &Span) -> Span {
+        Span { byte_range: self.byte_range.start..other.byte_range.end.max(self.byte_range.end), ..self.clone() }
+    }
+}
@@ -1,17 +1,32 @@
+use super::span::Span;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Illegal(String), Eof, Ident(String), Int(i64),
+    Illegal(String), Eof, Ident(String), Int(u64), Float(f64), Str(String),
     Assign, Plus, Minus, Asterisk, Slash,
-    Eq, NotEq, Lt, Gt,
-    LParen, RParen, Comma,
-    Function, Let, True, False, If, Then, Else, End, Return,
+    PlusAssign, MinusAssign, AsteriskAssign, SlashAssign,
+    Eq, NotEq, Lt, Gt, LtEq, GtEq, Concat, Hash, Tilde,
+    Percent, Caret, FloorDiv, Ampersand, Pipe, ShiftLeft, ShiftRight,
+    LParen, RParen, LBrace, RBrace, LBracket, RBracket, Comma, Colon, Semicolon, Dot, Arrow, Question,
+    Function, Let, Const, True, False, If, Then, Else, End, Return,
+    While, Do, For, In, Repeat, Until, Break, Continue, And, Or, Not, Record, Enum, Interface, Dyn, Match, With, Nil,
+}
+
+/// A token together with the source region it was read from.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token, pub span: Span,
 }
 
 pub fn lookup_ident(ident: &str) -> Token {
     match ident {
-        "function" => Token::Function, "local" => Token::Let, "true" => Token::True,
+        "function" => Token::Function, "local" => Token::Let, "const" => Token::Const, "true" => Token::True,
         "false" => Token::False, "if" => Token::If, "then" => Token::Then,
         "else" => Token::Else, "end" => Token::End, "return" => Token::Return,
+        "while" => Token::While, "do" => Token::Do, "for" => Token::For, "in" => Token::In, "repeat" => Token::Repeat,
+        "until" => Token::Until, "break" => Token::Break, "continue" => Token::Continue,
+        "and" => Token::And, "or" => Token::Or, "not" => Token::Not, "record" => Token::Record,
+        "enum" => Token::Enum, "interface" => Token::Interface, "dyn" => Token::Dyn, "match" => Token::Match, "with" => Token::With, "nil" => Token::Nil,
         _ => Token::Ident(ident.to_string()),
     }
 }
@@ -0,0 +1,242 @@
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntType { I8, I16, I32, I64, U8, U16, U32, U64 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatType { F32, F64 }
+
+/// A constraint on the types a type parameter stands for, as in `<T: Number>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bound {
+    /// Numbers, which support arithmetic and ordering.
+    Number,
+    /// Numbers and strings, which can be ordered and compared.
+    Ordered,
+    /// The types implementing the named interface, whose methods can be called.
+    Interface(String),
+}
+
+/// The type of a Kita value as determined by semantic analysis.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Type {
+    Int(IntType), Float(FloatType), Bool, String, Void,
+    Function { params: Vec<Type>, ret: Box<Type> },
+    /// A growable array `{T}`, or with a `size`, a fixed-size array `{T; N}`.
+    /// Arrays are references: assigning one shares its elements.
+    Array { elem: Box<Type>, size: Option<u64> },
+    /// A view of consecutive elements of an array, `[T]`.
+    Slice(Box<Type>),
+    /// A record type, named by its declaration. Records are references, like arrays.
+    Record(String),
+    /// An enum type, named by its declaration. Its values are references too.
+    Enum(String),
+    /// `T?`, a value of type `T` or nil.
+    Optional(Box<Type>),
+    /// `Result<T, E>`, the value of type `T` of an operation that succeeded,
+    /// or the error of type `E` of one that failed.
+    Result { ok: Box<Type>, err: Box<Type> },
+    /// The values `(T, U, ...)` of a function that returns more than one.
+    /// Only a destructuring `local` or a `return` can take them apart.
+    Tuple(Vec<Type>),
+    /// `Name<T, ...>`, an instance of a generic record type.
+    Generic { name: String, args: Vec<Type> },
+    /// A type parameter of the generic function or record being declared,
+    /// standing for any type that satisfies its `bound`. Monomorphization
+    /// replaces it with the type it stands for in each copy.
+    Param { name: String, bound: Option<Bound> },
+    /// `dyn Interface`, a value of any type implementing the interface, whose
+    /// methods are looked up at run time.
+    Dyn(String),
+    /// An inference variable, standing for a type that semantic analysis has
+    /// yet to determine. None are left once analysis has finished.
+    Var(u32),
+    /// Accepts any type, as the parameter of builtins such as `print` does.
+    /// Compatible with every other type.
+    #[default]
+    Unknown,
+    /// The type of an expression that failed to check. Compatible with every
+    /// other type, so that one mistake is not reported again wherever the
+    /// expression's value is used.
+    Error,
+}
+
+impl IntType {
+    pub const ALL: [IntType; 8] = [IntType::I8, IntType::I16, IntType::I32, IntType::I64, IntType::U8, IntType::U16, IntType::U32, IntType::U64];
+
+    pub fn bits(self) -> u32 {
+        match self { IntType::I8 | IntType::U8 => 8, IntType::I16 | IntType::U16 => 16, IntType::I32 | IntType::U32 => 32, IntType::I64 | IntType::U64 => 64 }
+    }
+
+    pub fn is_signed(self) -> bool { matches!(self, IntType::I8 | IntType::I16 | IntType::I32 | IntType::I64) }
+
+    pub fn contains(self, value: i128) -> bool {
+        let bits = self.bits();
+        if self.is_signed() { (-(1i128 << (bits - 1))..(1i128 << (bits - 1))).contains(&value) } else { (0..(1i128 << bits)).contains(&value) }
+    }
+
+    /// Whether every value of `self` is representable in `other`.
+    fn fits_in(self, other: IntType) -> bool {
+        match (self.is_signed(), other.is_signed()) {
+            (true, true) | (false, false) => self.bits() <= other.bits(),
+            (false, true) => self.bits() < other.bits(),
+            (true, false) => false,
+        }
+    }
+}
+
+impl Type {
+    pub const I64: Type = Type::Int(IntType::I64);
+    pub const F64: Type = Type::Float(FloatType::F64);
+
+    /// Looks up a built-in type by the name used in source code.
+    pub fn from_name(name: &str) -> Option<Type> {
+        IntType::ALL.into_iter().map(Type::Int).chain([Type::Float(FloatType::F32), Type::F64, Type::Bool, Type::String])
+            .find(|ty| ty.to_string() == name)
+    }
+
+    pub fn compatible(&self, other: &Type) -> bool {
+        matches!(self, Type::Unknown | Type::Error) || matches!(other, Type::Unknown | Type::Error) || self == other
+    }
+
+    pub fn is_numeric(&self) -> bool { matches!(self, Type::Int(_) | Type::Float(_) | Type::Param { bound: Some(Bound::Number), .. } | Type::Unknown | Type::Error) }
+
+    /// Whether values of `self` can be ordered with `<` and the like.
+    pub fn is_ordered(&self) -> bool { matches!(self, Type::String | Type::Param { bound: Some(Bound::Ordered), .. }) || self.is_numeric() }
+
+    /// Whether `self` satisfies `bound`. Records and enums implement an
+    /// interface by declaring so, which only semantic analysis knows of.
+    pub fn satisfies(&self, bound: &Bound) -> bool {
+        match bound {
+            Bound::Number => self.is_numeric(),
+            Bound::Ordered => self.is_ordered(),
+            Bound::Interface(name) => match self {
+                Type::Dyn(interface) | Type::Param { bound: Some(Bound::Interface(interface)), .. } => interface == name,
+                ty => matches!(ty, Type::Unknown | Type::Error),
+            },
+        }
+    }
+
+    /// `self` with the type parameters named in `args` replaced by their types.
+    pub fn substitute(&self, args: &HashMap<String, Type>) -> Type {
+        let each = |types: &[Type]| types.iter().map(|ty| ty.substitute(args)).collect();
+        match self {
+            Type::Param { name, .. } => args.get(name).cloned().unwrap_or_else(|| self.clone()),
+            Type::Function { params, ret } => Type::Function { params: each(params), ret: Box::new(ret.substitute(args)) },
+            Type::Array { elem, size } => Type::Array { elem: Box::new(elem.substitute(args)), size: *size },
+            Type::Slice(elem) => Type::Slice(Box::new(elem.substitute(args))),
+            Type::Optional(inner) => Type::Optional(Box::new(inner.substitute(args))),
+            Type::Result { ok, err } => Type::Result { ok: Box::new(ok.substitute(args)), err: Box::new(err.substitute(args)) },
+            Type::Tuple(elems) => Type::Tuple(each(elems)),
+            Type::Generic { name, args: inner } => Type::Generic { name: name.clone(), args: each(inner) },
+            ty => ty.clone(),
+        }
+    }
+
+    pub fn is_integer(&self) -> bool { matches!(self, Type::Int(_) | Type::Unknown | Type::Error) }
+
+    /// The element type of an array or slice.
+    pub fn element(&self) -> Option<&Type> {
+        match self { Type::Array { elem, .. } | Type::Slice(elem) => Some(elem), _ => None }
+    }
+
+    /// Whether values of `self` are references to shared data: arrays, slices,
+    /// records and enums.
+    pub fn is_reference(&self) -> bool { matches!(self, Type::Array { .. } | Type::Slice(_) | Type::Record(_) | Type::Generic { .. } | Type::Enum(_)) }
+
+    /// The signature of a method every value of type `self` has, not counting
+    /// `self`: `len`, `upper`, `lower` and `sub` on strings, and `len` on
+    /// arrays and slices.
+    pub fn builtin_method(&self, name: &str) -> Option<Type> {
+        let (params, ret) = match (self, name) {
+            (Type::String | Type::Array { .. } | Type::Slice(_), "len") => (vec![], Type::I64),
+            (Type::String, "upper" | "lower") => (vec![], Type::String),
+            (Type::String, "sub") => (vec![Type::I64, Type::I64], Type::String),
+            _ => return None,
+        };
+        Some(Type::Function { params, ret: Box::new(ret) })
+    }
+
+    /// The type arithmetic on `self` and `other` is carried out in: integers
+    /// widen to the larger type, and any float makes the result a float.
+    /// Returns `None` for non-numbers and for integer mixes (such as `i64` with
+    /// `u64`) that have no lossless common type.
+    pub fn promote(&self, other: &Type) -> Option<Type> {
+        match (self, other) {
+            (Type::Error, _) | (_, Type::Error) => Some(Type::Error),
+            (Type::Unknown, t) | (t, Type::Unknown) if t.is_numeric() => Some(t.clone()),
+            (Type::Int(a), Type::Int(b)) => {
+                if a.fits_in(*b) { Some(Type::Int(*b)) } else if b.fits_in(*a) { Some(Type::Int(*a)) } else { None }
+            }
+            // A type parameter only mixes with itself.
+            (Type::Param { .. }, _) | (_, Type::Param { .. }) => Some(self.clone()).filter(|_| self == other && self.is_numeric()),
+            (Type::Float(FloatType::F64), t) | (t, Type::Float(FloatType::F64)) if t.is_numeric() => Some(Type::F64),
+            (Type::Float(FloatType::F32), t) | (t, Type::Float(FloatType::F32)) if t.is_numeric() => Some(Type::Float(FloatType::F32)),
+            _ => None,
+        }
+    }
+
+    /// Whether a value of type `self` may be used where `target` is expected
+    /// without an explicit conversion: widening integer conversions, integer
+    /// to float and `f32` to `f64`.
+    pub fn converts_to(&self, target: &Type) -> bool {
+        if self.compatible(target) { return true; }
+        match (self, target) {
+            (Type::Int(a), Type::Int(b)) => a.fits_in(*b),
+            (Type::Int(_), Type::Float(_)) => true,
+            (Type::Float(FloatType::F32), Type::Float(FloatType::F64)) => true,
+            (Type::Array { elem, .. }, Type::Slice(target)) => elem == target,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self { Bound::Number => "Number", Bound::Ordered => "Ordered", Bound::Interface(name) => name })
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", if self.is_signed() { "i" } else { "u" }, self.bits())
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int(int) => write!(f, "{}", int),
+            Type::Float(FloatType::F32) => f.write_str("f32"),
+            Type::Float(FloatType::F64) => f.write_str("f64"),
+            Type::Bool => f.write_str("bool"),
+            Type::String => f.write_str("string"),
+            Type::Void => f.write_str("void"),
+            Type::Function { params, ret } => {
+                let params: Vec<String> = params.iter().map(ToString::to_string).collect();
+                write!(f, "function({})", params.join(", "))?;
+                if **ret != Type::Void { write!(f, ": {}", ret)?; }
+                Ok(())
+            }
+            Type::Array { elem, size: None } => write!(f, "{{{}}}", elem),
+            Type::Array { elem, size: Some(size) } => write!(f, "{{{}; {}}}", elem, size),
+            Type::Slice(elem) => write!(f, "[{}]", elem),
+            Type::Record(name) | Type::Enum(name) => f.write_str(name),
+            Type::Optional(inner) => write!(f, "{}?", inner),
+            Type::Result { ok, err } => write!(f, "Result<{}, {}>", ok, err),
+            Type::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(ToString::to_string).collect();
+                write!(f, "({})", elems.join(", "))
+            }
+            Type::Generic { name, args } => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{}<{}>", name, args.join(", "))
+            }
+            Type::Param { name, .. } => f.write_str(name),
+            Type::Dyn(name) => write!(f, "dyn {}", name),
+            Type::Var(_) => f.write_str("_"),
+            Type::Unknown => f.write_str("unknown"),
+            Type::Error => f.write_str("{error}"),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+//! Compiles each program in `tests/parse_errors/` and `tests/semantic_errors/`
+//! and compares the reported diagnostics with the `.errors` file next to it,
+//! one diagnostic per line. Set `KITA_BLESS=1` to rewrite the expectations.
+
+use std::{env, fs, path::Path};
+
+use kita_bin::frontend::{diagnostic::Diagnostic, lexer::Lexer, parser::Parser, sema::SemanticAnalyzer};
+
+/// The diagnostics of the first phase that reports any: parsing, then analysis.
+fn diagnostics(path: &Path) -> Vec<Diagnostic> {
+    let source = fs::read_to_string(path).unwrap();
+    let name = path.file_name().unwrap().to_string_lossy();
+    let mut parser = Parser::new(Lexer::with_file(source, &name));
+    let mut program = parser.parse_program();
+    if !parser.errors.is_empty() { return parser.errors; }
+    SemanticAnalyzer::new().analyze(&mut program)
+}
+
+fn check_corpus(corpus: &str) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(corpus);
+    let mut programs: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).filter(|p| p.extension().is_some_and(|e| e == "ki")).collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no test programs in {}", dir.display());
+
+    let mut failures = Vec::new();
+    for program in &programs {
+        let actual: String = diagnostics(program).iter().map(|d| format!("{}\n", d)).collect();
+        let expected_path = program.with_extension("errors");
+        if env::var_os("KITA_BLESS").is_some() { fs::write(&expected_path, &actual).unwrap(); continue; }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!("{}:\n--- expected\n{}--- actual\n{}", program.display(), expected, actual));
+        }
+    }
+    assert!(failures.is_empty(), "diagnostics differ from expectations:\n\n{}", failures.join("\n"));
+}
+
+#[test]
+fn broken_programs_report_each_syntax_error_once() { check_corpus("parse_errors"); }
+
+#[test]
+fn analysis_reports_all_errors_and_warnings() { check_corpus("semantic_errors"); }
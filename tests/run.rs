@@ -0,0 +1,85 @@
+//! Compiles each program in `tests/run/` to C, builds it with the system C
+//! compiler and runs it, comparing what it printed and its exit status with
+//! the `.out` file next to it. A program that fails to compile is compared
+//! by its rendered diagnostics instead. Programs whose name ends in
+//! `_unchecked` are built without bounds checks. Set `KITA_BLESS=1` to
+//! rewrite the expectations, and `KITA_CC` to pick the C compiler. A program
+//! still running after ten seconds is stopped.
+
+use std::{env, fmt::Write, fs, path::Path, process::{Command, Stdio}, thread, time::{Duration, Instant}};
+
+use kita_bin::backend::codegen_c::CTranspiler;
+use kita_bin::frontend::{lexer::Lexer, monomorphize::monomorphize, parser::Parser, sema::SemanticAnalyzer};
+
+/// The C source of the program at `path`, or its rendered diagnostics if it
+/// has errors.
+fn transpile(path: &Path) -> Result<String, String> {
+    let source = fs::read_to_string(path).unwrap();
+    let name = path.file_name().unwrap().to_string_lossy();
+    let mut parser = Parser::new(Lexer::with_file(source.clone(), &name));
+    let mut program = parser.parse_program();
+    let mut diagnostics = if parser.errors.is_empty() { SemanticAnalyzer::new().analyze(&mut program) } else { parser.errors };
+    if !diagnostics.iter().any(|d| d.is_error()) { diagnostics.extend(monomorphize(&mut program)); }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err(diagnostics.iter().map(|d| format!("{}\n", d.render(&source))).collect());
+    }
+    let unchecked = path.file_stem().unwrap().to_string_lossy().ends_with("_unchecked");
+    Ok(CTranspiler::new().with_bounds_checks(!unchecked).transpile(program).unwrap())
+}
+
+/// Builds and runs the program at `path` in `dir`, and describes what it did:
+/// its output, then what it wrote to stderr and its exit status, if any.
+fn run(path: &Path, dir: &Path) -> String {
+    let c_code = match transpile(path) {
+        Ok(c_code) => c_code,
+        Err(diagnostics) => return format!("[diagnostics]\n{}", diagnostics),
+    };
+    let stem = path.file_stem().unwrap().to_string_lossy();
+    let (c_file, executable) = (dir.join(format!("{}.c", stem)), dir.join(&*stem));
+    fs::write(&c_file, c_code).unwrap();
+    let compiler = env::var("KITA_CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(&compiler).arg("-O2").arg("-o").arg(&executable).arg(&c_file).arg("-lm").output()
+        .unwrap_or_else(|e| panic!("cannot run the C compiler '{}': {}", compiler, e));
+    if !built.status.success() { return format!("[C compilation failed]\n{}", String::from_utf8_lossy(&built.stderr)); }
+    let mut child = Command::new(&executable).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while child.try_wait().unwrap().is_none() {
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            return "[timed out]\n".to_string();
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    let ran = child.wait_with_output().unwrap();
+    let mut actual = String::from_utf8_lossy(&ran.stdout).into_owned();
+    if !ran.stderr.is_empty() { write!(actual, "[stderr]\n{}", String::from_utf8_lossy(&ran.stderr)).unwrap(); }
+    match ran.status.code() {
+        Some(0) => {}
+        Some(code) => writeln!(actual, "[exit status {}]", code).unwrap(),
+        None => writeln!(actual, "[killed by a signal]").unwrap(),
+    }
+    actual
+}
+
+#[test]
+fn programs_print_what_they_should() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("run");
+    let mut programs: Vec<_> = fs::read_dir(&root).unwrap().map(|entry| entry.unwrap().path()).filter(|p| p.extension().is_some_and(|e| e == "ki")).collect();
+    programs.sort();
+    assert!(!programs.is_empty(), "no test programs in {}", root.display());
+    let dir = env::temp_dir().join(format!("kita-run-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for program in &programs {
+        let actual = run(program, &dir);
+        let expected_path = program.with_extension("out");
+        if env::var_os("KITA_BLESS").is_some() { fs::write(&expected_path, &actual).unwrap(); continue; }
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!("{}:\n--- expected\n{}--- actual\n{}", program.display(), expected, actual));
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(failures.is_empty(), "programs behaved differently than expected:\n\n{}", failures.join("\n"));
+}